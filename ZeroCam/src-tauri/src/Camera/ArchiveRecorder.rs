@@ -0,0 +1,167 @@
+use crate::Camera::CameraController::CameraController;
+use crate::Camera::MotionListener::getParkedState;
+use crate::Camera::SegmentRing::{Segment, SegmentRing, SegmentState};
+use crate::Config;
+use crate::Config::{ArchiveMode, ConfigFile};
+use crate::Storage::RetentionPolicy::RetentionPolicy;
+use crate::Storage::StorageRoot::StorageRoot;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+static ARCHIVE_MODE: Mutex<ArchiveMode> = Mutex::new(ArchiveMode::Off);
+
+/// Keeps every completed segment while archiving is on, staged in a hidden directory beside the
+/// clips and joined into one archive clip per period, so the retention policy evicts archives
+/// like any other clip
+pub struct ArchiveRecorder {
+  cameraController: CameraController,
+  retentionPolicy : Arc<RetentionPolicy>,
+  config          : ConfigFile,
+}
+
+impl ArchiveRecorder {
+  pub async fn new() -> Result<ArchiveRecorder, Box<dyn Error>> {
+    Ok(Self {
+      cameraController: CameraController::new().await?,
+      retentionPolicy : Arc::new(RetentionPolicy::new().await?),
+      config          : Config::getConfig().await?,
+    })
+  }
+
+  /// One archiving loop per camera, segments left staged by a previous run are archived first
+  pub fn start(self: Arc<Self>) {
+    setArchiveMode(self.config.archive.mode);
+    for camera in self.config.camera_input.iter().map(|c| c.name.clone()) {
+      let recorder = self.clone();
+      tokio::spawn(async move {
+        recorder.finish(&camera, None).await;
+        recorder.run(camera).await;
+      });
+    }
+  }
+
+  async fn run(self: Arc<Self>, camera: String) {
+    let segmentSizeSec: u64 = self.config.clip.segment_size_sec.parse().unwrap_or(5);
+    let ring = SegmentRing::forCamera(&camera);
+    let mut lastIndex: Option<u64> = None;
+    let mut period: Option<DateTime<Utc>> = None; // start of the period being staged
+    loop {
+      tokio::time::sleep(Duration::from_secs(segmentSizeSec)).await; //well inside the ring so nothing is overwritten before it is staged
+      let archiving = isArchiving();
+      let segments: Vec<Segment> = ring.segments()
+        .into_iter()
+        .filter(|s| s.state == SegmentState::Completed && lastIndex.map(|i| s.index > i).unwrap_or(true))
+        .collect();
+      for segment in segments {
+        lastIndex = Some(segment.index);
+        if !archiving { continue; }
+
+        let segmentPeriod = self.periodStart(segment.startTime);
+        if let Some(finished) = period.filter(|p| *p != segmentPeriod) {
+          self.finishInBackground(&camera, finished);
+        }
+        period = Some(segmentPeriod);
+        if let Err(e) = self.stage(&camera, &ring, &segment).await {
+          error!("Failed to archive segment {} of camera {}: {}", segment.fileName, camera, e);
+        }
+      }
+      if let Some(finished) = period.filter(|_| !archiving) {
+        period = None;
+        self.finishInBackground(&camera, finished); //eg. parked, the archive ends with the drive
+      }
+    }
+  }
+
+  fn periodStart(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+    let periodSec = (self.config.archive.period_min.max(1) * 60) as i64;
+    DateTime::from_timestamp(time.timestamp() - time.timestamp().rem_euclid(periodSec), 0).unwrap_or(time)
+  }
+
+  /// Copies a completed segment out of the ring before ffmpeg wraps around to it, named by its start time
+  async fn stage(&self, camera: &str, ring: &SegmentRing, segment: &Segment) -> Result<(), Box<dyn Error>> {
+    self.retentionPolicy.enforce(segment.sizeB).await?; //the staged footage is on the clips disk too
+    let stagingPath = stagingPath(camera);
+    fs::create_dir_all(&stagingPath)?;
+    let extension = self.config.clip.segment_format.extension();
+    fs::copy(ring.path(segment), stagingPath.join(format!("{}.{}", segment.startTime.timestamp_millis(), extension)))?;
+    Ok(())
+  }
+
+  /// Joins a period on its own task, staging has to keep copying out of the ring meanwhile
+  fn finishInBackground(self: &Arc<Self>, camera: &str, period: DateTime<Utc>) {
+    let recorder = self.clone();
+    let camera = camera.to_string();
+    tokio::spawn(async move {
+      recorder.finish(&camera, Some(period)).await;
+    });
+  }
+
+  /// Archives what is staged for the camera in the period, or everything when None, and removes it
+  /// from the staging directory. Segments of the next period may be staged beside it meanwhile
+  async fn finish(&self, camera: &str, period: Option<DateTime<Utc>>) {
+    let staged: Vec<(DateTime<Utc>, PathBuf)> = stagedSegments(camera)
+      .into_iter()
+      .filter(|(start, _)| period.map(|p| self.periodStart(*start) == p).unwrap_or(true))
+      .collect();
+    let (Some((from, _)), Some((lastStart, _))) = (staged.first(), staged.last()) else { return; };
+    let segmentSizeSec: i64 = self.config.clip.segment_size_sec.parse().unwrap_or(5);
+    let to = *lastStart + TimeDelta::seconds(segmentSizeSec);
+    let paths: Vec<PathBuf> = staged.iter().map(|(_, path)| path.clone()).collect();
+
+    info!("Archiving {} segments of camera {} from {}", paths.len(), camera, from);
+    let archived = self.cameraController.archive(camera, &paths, *from, to).await.map_err(|e| e.to_string());
+    match archived {
+      Ok(result) => {
+        info!("Archive saved: {}", result.fileName);
+        for path in paths {
+          let _ = fs::remove_file(path);
+        }
+      }
+      Err(e) => error!("Archive of camera {} failed, its segments are kept and archived on the next start: {}", camera, e),
+    }
+  }
+}
+
+/// The leading dot keeps staged segments out of the catalog and backup until they are archived
+fn stagingPath(camera: &str) -> PathBuf {
+  StorageRoot::global().clipsPath().join(format!(".archive-{}", camera))
+}
+
+/// Staged segments oldest first with their start times
+fn stagedSegments(camera: &str) -> Vec<(DateTime<Utc>, PathBuf)> {
+  let mut staged: Vec<(DateTime<Utc>, PathBuf)> = fs::read_dir(stagingPath(camera))
+    .map(|entries| entries
+      .filter_map(|e| e.ok())
+      .filter_map(|e| {
+        let startMs: i64 = e.path().file_stem()?.to_str()?.parse().ok()?;
+        Some((DateTime::from_timestamp_millis(startMs)?, e.path()))
+      })
+      .collect())
+    .unwrap_or_default();
+  staged.sort_by_key(|(start, _)| *start);
+  staged
+}
+
+pub fn setArchiveMode(mode: ArchiveMode) {
+  *ARCHIVE_MODE.lock().unwrap() = mode;
+  info!("Archive Mode: {:?}", mode)
+}
+
+pub fn getArchiveMode() -> ArchiveMode {
+  *ARCHIVE_MODE.lock().unwrap()
+}
+
+/// Whether completed segments are being archived right now
+pub fn isArchiving() -> bool {
+  match getArchiveMode() {
+    ArchiveMode::Off     => false,
+    ArchiveMode::Always  => true,
+    ArchiveMode::Driving => !getParkedState(),
+  }
+}
@@ -0,0 +1,55 @@
+use crate::Camera::FfmpegPipeline::FfmpegInput;
+use crate::Config::{Audio, AudioSource};
+
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+impl AudioSource {
+  pub fn input(&self, sampleRate: &str) -> FfmpegInput {
+    match self {
+      AudioSource::Alsa { device } => FfmpegInput::new(device)
+        .format("alsa"),
+      AudioSource::Pulse { device } => FfmpegInput::new(device)
+        .format("pulse"),
+      AudioSource::Sine { frequency } => FfmpegInput::new(format!("sine=frequency={}:sample_rate={}", frequency, sampleRate))
+        .flag("-re") // generate in real time alongside the camera
+        .format("lavfi"),
+      AudioSource::Anullsrc => silence(sampleRate),
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      AudioSource::Alsa { device }     => format!("alsa device {}", device),
+      AudioSource::Pulse { device }    => format!("pulse source {}", device),
+      AudioSource::Sine { frequency }  => format!("ffmpeg sine {}Hz", frequency),
+      AudioSource::Anullsrc            => "ffmpeg anullsrc".to_string(),
+    }
+  }
+}
+
+/// The pipeline input for the audio track, muted records silence instead of dropping the track so
+/// every segment has the same streams and clips spanning a toggle still concatenate
+pub fn audioInput(audio: &Audio) -> FfmpegInput {
+  match getMutedState() {
+    true  => silence(&audio.sample_rate),
+    false => audio.source.input(&audio.sample_rate),
+  }
+}
+
+fn silence(sampleRate: &str) -> FfmpegInput {
+  FfmpegInput::new(format!("anullsrc=channel_layout=mono:sample_rate={}", sampleRate))
+    .flag("-re")
+    .format("lavfi")
+}
+
+pub fn setMutedState(b: bool) {
+  MUTED.store(b, Ordering::Relaxed);
+  info!("Audio Muted State: {}", b.to_string())
+}
+
+pub fn getMutedState() -> bool {
+  MUTED.load(Ordering::Relaxed)
+}
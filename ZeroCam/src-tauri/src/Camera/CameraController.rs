@@ -1,205 +1,620 @@
+use crate::Camera::AudioSource::{self, audioInput};
+use crate::Camera::ClipPreview::{self, ClipPreviews};
+use crate::Camera::ClipScheduler::{ClipTrigger, TriggerSource};
+use crate::Camera::FfProbe;
+use crate::Camera::MotionFeed::MotionFeed;
+use crate::Camera::SegmentRing::{Segment, SegmentRing, SEGMENT_LIST_FILE_NAME};
+use crate::Camera::FfmpegPipeline::{FfmpegInput, FfmpegOutput, FfmpegPipeline};
 use crate::Config;
-use crate::Config::ConfigFile;
+use crate::Config::{CameraInput, ClipFormat, ConfigFile, Encoding, Orientation, Overlay};
+use crate::Process::Supervisor::{ChildSpec, Supervisor};
+use crate::Storage::ClipCatalog::{sha256File, ClipCatalog, ClipRecord, UploadState};
+use crate::Storage::ClipEncryption;
+use crate::Storage::ClipLedger::ClipLedger;
+use crate::Storage::RetentionPolicy::RetentionPolicy;
+use crate::Storage::StorageRoot::{moveFile, partPath, StorageRoot};
 
-use chrono::Utc;
-use log::{info};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
 use std::error::Error;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::{env, fs, thread, time};
-use std::time::SystemTime;
-use sysinfo::Disks;
-use thread::sleep;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs, time};
+use tokio::sync::broadcast::error::RecvError;
+
+/// A finished clip that ffmpeg exited cleanly on and ffprobe could read back
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipResult {
+  pub path        : String,
+  pub fileName    : String,
+  pub sizeB       : u64,
+  pub durationSec : f64,
+  pub segmentCount: usize,
+  pub streams     : Vec<String>,
+  pub posterPath  : Option<String>,
+  pub previewPath : Option<String>,
+}
 
 pub struct CameraController {
-  recordingSegmentsPath : String,
-  recordingPathsFilePath: String, // a file that stores the paths of files inside  LiveRecordings directory
-  clipsPath             : String,
-  config                : ConfigFile,
+  recordingPathsDirectory: String, // holds a file per camera that stores the paths of files inside its LiveRecording directory
+  config                 : ConfigFile,
+  retentionPolicy        : Arc<RetentionPolicy>,
 }
 
 impl CameraController {
   pub async fn new() -> Result<CameraController, Box<dyn Error>> {
     Ok(Self{
-      recordingSegmentsPath : "../lib/zerocam/LiveRecording/".to_string(),
-      recordingPathsFilePath: "../lib/zerocam/recordingPaths.txt".to_string(),
-      clipsPath             : "../lib/zerocam/Clips/".to_string(),
-      config                : Config::getConfig().await?
+      recordingPathsDirectory: "../lib/zerocam/".to_string(),
+      config                 : Config::getConfig().await?,
+      retentionPolicy        : Arc::new(RetentionPolicy::new().await?),
     })
   }
 
-  pub async fn clip(&self) -> Result<(), Box<dyn Error>> {
-    const GB: i64 = 1024 * 1024 * 1024;
-    fn getDiskSafeSpaceB(availableSpaceLimit: i64) -> i64 {
-      Disks::new_with_refreshed_list()
-        .list()[0]
-        .available_space() as i64
-        // - (GB * 319) // comment outside test case
-        - availableSpaceLimit // never allow system to have less than 1GB available space for stability
+  /// Clips every camera the trigger covers around the same moment, one camera failing doesn't
+  /// lose the others
+  pub async fn clip(&self, trigger: &ClipTrigger, cameras: &[String]) -> Result<Vec<ClipResult>, Box<dyn Error>> {
+    let cameras: Vec<&CameraInput> = cameras.iter()
+      .map(|name| self.config.camera(name).ok_or(format!("No camera is named {}", name)))
+      .collect::<Result<_, _>>()?;
+
+    let mut results: Vec<ClipResult> = Vec::new();
+    let mut lastError: Option<String> = None;
+    for camera in cameras { //one after another, the wait for footage after the event is only paid once
+      match self.clipCamera(camera, trigger).await.map_err(|e| e.to_string()) {
+        Ok(result) => results.push(result),
+        Err(e)     => {
+          error!("Clip of camera {} failed: {}", camera.name, e);
+          lastError = Some(format!("{}: {}", camera.name, e));
+        }
+      }
+    }
+    match (results.is_empty(), lastError) {
+      (true, Some(e)) => Err(e.into()),
+      _               => Ok(results),
+    }
+  }
+
+  async fn clipCamera(&self, camera: &CameraInput, trigger: &ClipTrigger) -> Result<ClipResult, Box<dyn Error>> {
+    let clipConfig = &self.config.clip;
+    let segmentSizeSec: i64 = clipConfig.segment_size_sec.parse()?;
+    let bufferSec: i64 = segmentSizeSec * clipConfig.segments.parse::<i64>()?;
+    let (windowStart, windowEnd) = self.clipWindow(trigger)?;
+    if (windowEnd - windowStart).num_seconds() + segmentSizeSec > bufferSec {
+      warn!("Clip window is longer than the {}sec recording buffer, the start will be cut short", bufferSec);
     }
 
-    info!("Clip scheduled, waiting for timer...");
-    sleep(time::Duration::from_secs(self.config.camera_input.clip.timer_before_clip_sec));
+    //the segment holding the end of the window is only usable once ffmpeg has moved on from it
+    let readyAt = windowEnd + TimeDelta::seconds(segmentSizeSec);
+    info!("Clip of camera {} scheduled, waiting for footage after the event...", camera.name);
+    let waitLimit = (readyAt - Utc::now()).to_std().unwrap_or_default() + time::Duration::from_secs(segmentSizeSec as u64 * 2);
+    let ring = SegmentRing::forCamera(&camera.name);
+    if !ring.waitForCompletedUntil(windowEnd, waitLimit).await {
+      warn!("Recording of camera {} did not reach the end of the clip window, clipping what exists", camera.name);
+    }
+
+    let recordingPathsFilePath = self.recordingPathsFilePath(camera);
+    let listed = self.makePathsForWritingFileAndGetOutputSize(camera, &ring, &recordingPathsFilePath, windowStart, windowEnd, trigger.frameAccurate).await.map_err(|e| e.to_string());
+    let result = match listed {
+      Ok((outputSizeB, segmentCount)) => self.writeClip(camera, trigger, &recordingPathsFilePath, outputSizeB, segmentCount).await.map_err(|e| e.to_string()),
+      Err(e)                          => Err(e),
+    };
+    self.removeBoundaryCuts(camera);
+    Ok(result?)
+  }
+
+  /// Joins segments staged out of the ring during one archive period into one archive clip
+  pub async fn archive(&self, camera: &str, stagedPaths: &[PathBuf], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ClipResult, Box<dyn Error>> {
+    let camera = self.config.camera(camera).ok_or(format!("No camera is named {}", camera))?;
+    let archivePathsFilePath = format!("{}archivePaths-{}.txt", self.recordingPathsDirectory, camera.name);
+    let pathsForWriting: Vec<String> = stagedPaths.iter().map(|p| concatEntry(&p.display().to_string(), None, None)).collect();
+    fs::write(&archivePathsFilePath, pathsForWriting.join("\n"))?;
+    let outputSizeB: u64 = stagedPaths.iter().filter_map(|p| fs::metadata(p).ok()).map(|m| m.len()).sum();
+
+    self.writeClip(camera, &ClipTrigger::archive(from, to), &archivePathsFilePath, outputSizeB as i64, stagedPaths.len()).await
+  }
 
-    let outputSizeB:i64 = self.makePathsForWritingFileAndGetOutputSize().await?;
+  /// Concatenates the listed segments into the clips directory, checks the result and catalogs it
+  /// with its previews
+  async fn writeClip(&self, camera: &CameraInput, trigger: &ClipTrigger, recordingPathsFilePath: &str, outputSizeB: i64, segmentCount: usize) -> Result<ClipResult, Box<dyn Error>> {
+    let clipConfig = &self.config.clip;
     info!("Clip outputSize: {:.0}MB", (outputSizeB as f64) / (1024.0 * 1024.0));
 
-    //this assumes the dashcam is not running multiple drives and if it is the app is deployed on
-    //the root file system
-    let mut diskSafeSpaceB:i64 = getDiskSafeSpaceB(self.config.camera_input.clip.disk_full_buffer_gb.clone() * GB);
-    info!("Safe disk space: {:.3}GB", (diskSafeSpaceB as f64) / (1024.0 * 1024.0 * 1024.0));
-    while outputSizeB > diskSafeSpaceB {
-      let oldestClipPath= self.getOldestLocalClip()?;
-      fs::remove_file(&oldestClipPath)?;
-      info!("Deleted oldest clip: {}", oldestClipPath.display());
-      sleep(time::Duration::from_secs(1)); //allow kernel time to finish deleting
-      diskSafeSpaceB = getDiskSafeSpaceB(self.config.camera_input.clip.disk_full_buffer_gb.clone() * GB);
-    }
-
-    let newFileName = self.clipsPath.clone() + &*Utc::now().to_string() + ".mp4";
-    info!("Concatenating recordings to {}", &newFileName);
-    Command::new("ffmpeg")
-      .stdin(Stdio::null())
-      .stdout(Stdio::null()) //peace
-      .stderr(Stdio::null()) //and quiet :))
-      .arg("-f"   ).arg("concat"                           ) //input existing files
-      .arg("-safe").arg("0"                                ) //disables safety to allow full path use
-      .arg("-i"   ).arg(self.recordingPathsFilePath.clone()) //input list of files to be concatenated
-      .arg("-c").arg("copy")
-      .arg(newFileName)
-      .spawn().unwrap();
-    Ok(())
-  }
-
-  async fn makePathsForWritingFileAndGetOutputSize(&self) -> Result<i64, Box<dyn Error>> {
-    let mut outputs: Vec<(SystemTime, u64, String)> = fs::read_dir(self.recordingSegmentsPath.clone())?
-      .filter_map(|e| {
-        let entry = e.ok()?;
-        let meta = &entry.metadata().ok()?;
-        let modified = meta.modified().ok()?;
-        let size = meta.len();
-        Some((modified, size, entry.file_name().into_string().unwrap()))
-      })
+    self.retentionPolicy.enforce(outputSizeB as u64).await?; //make room before writing
+
+    let (clipFileName, partFileName) = self.clipFileNames(camera, trigger);
+    info!("Concatenating recordings to {}", &clipFileName);
+    if let Err(e) = buildConcatPipeline(recordingPathsFilePath, &partFileName, clipConfig.clip_format).run().await {
+      let _ = fs::remove_file(&partFileName);
+      return Err(format!("Concat failed: {}", e).into());
+    }
+    self.finishClip(camera, trigger, &partFileName, clipFileName, segmentCount).await
+  }
+
+  /// Assembles one day of parked timelapse frames into a clip at fps frames a second
+  pub async fn timelapse(&self, camera: &str, framesDirectory: &Path, from: DateTime<Utc>, to: DateTime<Utc>, fps: u32) -> Result<ClipResult, Box<dyn Error>> {
+    let camera = self.config.camera(camera).ok_or(format!("No camera is named {}", camera))?;
+    let frames: Vec<fs::Metadata> = fs::read_dir(framesDirectory)?
+      .filter_map(|e| e.ok()?.metadata().ok())
+      .filter(|m| m.is_file())
       .collect();
+    let inputSizeB: u64 = frames.iter().map(|m| m.len()).sum();
+    self.retentionPolicy.enforce(inputSizeB).await?; //the video is rarely bigger than its jpeg frames
+
+    let trigger = ClipTrigger::timelapse(from, to);
+    let (clipFileName, partFileName) = self.clipFileNames(camera, &trigger);
+    info!("Assembling {} timelapse frames to {}", frames.len(), &clipFileName);
+    let timelapse = buildTimelapsePipeline(&framesDirectory.display().to_string(), fps, &camera.encoding, self.config.clip.clip_format, &partFileName);
+    if let Err(e) = timelapse.run().await {
+      let _ = fs::remove_file(&partFileName);
+      return Err(format!("Timelapse failed: {}", e).into());
+    }
+    self.finishClip(camera, &trigger, &partFileName, clipFileName, frames.len()).await
+  }
 
-    outputs = outputs.clone().into_iter().filter(|(_, _, name)| name != ".gitkeep").collect();
+  /// The clip's file name and the hidden path it is written to until it has been checked
+  fn clipFileNames(&self, camera: &CameraInput, trigger: &ClipTrigger) -> (String, String) {
+    let label = match trigger.source {
+      TriggerSource::Archive   => format!("{}_archive", camera.name),
+      TriggerSource::Timelapse => format!("{}_timelapse", camera.name),
+      _                        => camera.name.clone(),
+    };
+    let clipFileName = label + "_" + &*trigger.time.to_string() + "." + self.config.clip.clip_format.extension();
+    let partFileName = partPath(&StorageRoot::global().clipsPath().join(&clipFileName)).display().to_string();
+    (clipFileName, partFileName)
+  }
 
-    let outputSize: u64 = outputs.clone()
-      .iter()
-      .map(|(_, size, _)| size)
-      .sum();
+  /// Checks the written clip, moves it into place and catalogs it with its previews
+  async fn finishClip(&self, camera: &CameraInput, trigger: &ClipTrigger, partFileName: &str, clipFileName: String, segmentCount: usize) -> Result<ClipResult, Box<dyn Error>> {
+    let clipConfig = &self.config.clip;
+    let encryptLocal = self.config.encryption.enabled && !self.config.encryption.keep_local_plaintext;
+    let clipFileName = match encryptLocal {
+      true  => ClipEncryption::encryptedFileName(&clipFileName),
+      false => clipFileName,
+    };
+    let newFileName = StorageRoot::global().clipsPath().display().to_string() + &clipFileName;
+    let verified = verifyClip(partFileName, segmentCount).await.map_err(|e| e.to_string());
+    let mut result = match verified {
+      Ok(result) => result,
+      Err(e)     => {
+        let _ = fs::remove_file(partFileName); //don't leave a broken clip behind
+        return Err(e.into());
+      }
+    };
+    let plainSha256 = self.recordInLedger(partFileName, ClipEncryption::plainFileName(&clipFileName), result.sizeB).await?;
+    match encryptLocal {
+      true  => result.sizeB = self.encryptClip(partFileName, &newFileName).await?,
+      false => {
+        let (part, new) = (PathBuf::from(partFileName), PathBuf::from(&newFileName));
+        tokio::task::spawn_blocking(move || moveFile(&part, &new).map_err(|e| e.to_string())).await??; //the storage root may have switched while it was written
+      }
+    }
+    result.path     = newFileName;
+    result.fileName = clipFileName;
+    info!("Clip finalized: {} ({:.1}MB, {:.1}sec from {} segments)",
+      result.fileName, result.sizeB as f64 / (1024.0 * 1024.0), result.durationSec, result.segmentCount);
+    if camera.audio.is_some() && !result.streams.iter().any(|s| s == "audio") {
+      warn!("Clip {} has no audio stream although audio is configured", result.fileName);
+    }
 
-    outputs.sort_by_key(|(time, _, _)| *time);
-    let sortedOutputs: Vec<String>              = outputs.into_iter().map(|(_, _, name)| name).collect();
-    let sortedOutputsWithFullPaths: Vec<String> = sortedOutputs.iter().map({|output|
-      "file '".to_owned()
-        + "LiveRecording/"
-        + &output
-        + "'"
-    }).collect();
+    let eventSec = match trigger.range {
+      Some(_) => result.durationSec / 2.0, //exports, archives and timelapses have no event, preview the middle
+      None    => (clipConfig.pre_event_sec as f64).min(result.durationSec),
+    };
+    let previews = match encryptLocal {
+      true  => Err("local clips are encrypted".to_string()), //a plaintext poster would give the clip away
+      false => ClipPreview::generate(&result.path, &result.fileName, eventSec).await.map_err(|e| e.to_string()),
+    };
+    match &previews {
+      Ok(previews) => {
+        result.posterPath  = Some(ClipPreview::previewPath(&previews.posterFileName).display().to_string());
+        result.previewPath = Some(ClipPreview::previewPath(&previews.previewFileName).display().to_string());
+      }
+      Err(e) => info!("Clip {} saved without previews: {}", result.fileName, e),
+    }
+
+    let sha256 = match encryptLocal {
+      true  => None, //the catalog has the hash of the stored file
+      false => Some(plainSha256),
+    };
+    self.catalogClip(camera, trigger, &result, previews.ok(), sha256).await?;
+    Ok(result)
+  }
 
-    let pathsForWriting = sortedOutputsWithFullPaths.join("\n");
-    fs::write(self.recordingPathsFilePath.clone(), pathsForWriting)?;
+  /// Hashes the checked clip before anything else touches it and chains the hash onto the ledger, a
+  /// clip that can't be recorded is still kept
+  async fn recordInLedger(&self, partFileName: &str, clipFileName: &str, sizeB: u64) -> Result<String, Box<dyn Error>> {
+    let partPath = PathBuf::from(partFileName);
+    let sha256 = tokio::task::spawn_blocking(move || sha256File(&partPath).map_err(|e| e.to_string()))
+      .await??;
+    if let Err(e) = ClipLedger::global().append(clipFileName, &sha256, sizeB, self.config.ledger.sign) {
+      error!("Clip {} was not recorded in the ledger: {}", clipFileName, e);
+    }
+    Ok(sha256)
+  }
+
+  /// Encrypts the checked plaintext clip into its final name and removes the plaintext, returns the encrypted size
+  async fn encryptClip(&self, partFileName: &str, newFileName: &str) -> Result<u64, Box<dyn Error>> {
+    let from = PathBuf::from(partFileName);
+    let to = PathBuf::from(newFileName);
+    let encryptedPart = partPath(&to);
+    let recipient = self.config.encryption.recipient.clone();
+    let (plain, encrypted) = (from.clone(), encryptedPart.clone());
+    let result = tokio::task::spawn_blocking(move || ClipEncryption::encryptFile(&plain, &encrypted, &recipient).map_err(|e| e.to_string()))
+      .await
+      .map_err(|e| e.to_string())
+      .and_then(|r| r);
+    let _ = fs::remove_file(&from); //the plaintext never reaches the clips directory
+    if let Err(e) = result {
+      let _ = fs::remove_file(&encryptedPart);
+      return Err(format!("Clip encryption failed: {}", e).into());
+    }
+    fs::rename(&encryptedPart, &to)?;
+    Ok(fs::metadata(&to)?.len())
+  }
+
+  /// The configured window around the trigger, or the exact range an export asked for
+  fn clipWindow(&self, trigger: &ClipTrigger) -> Result<(DateTime<Utc>, DateTime<Utc>), Box<dyn Error>> {
+    match &trigger.range {
+      Some(range) if range.from >= range.to => Err(format!("Export range {} to {} is empty", range.from, range.to).into()),
+      Some(range) => Ok((range.from, range.to)),
+      None        => Ok((
+        trigger.time - TimeDelta::seconds(self.config.clip.pre_event_sec as i64),
+        trigger.time + TimeDelta::seconds(self.config.clip.post_event_sec as i64),
+      )),
+    }
+  }
+
+  fn recordingPathsFilePath(&self, camera: &CameraInput) -> String {
+    format!("{}recordingPaths-{}.txt", self.recordingPathsDirectory, camera.name)
+  }
 
-    Ok(outputSize as i64)
+  /// Where the re-encoded start or end of a frame accurate clip is written before concatenating
+  fn boundaryCutPath(&self, camera: &CameraInput, end: &str) -> String {
+    format!("{}boundary-{}-{}.{}", self.recordingPathsDirectory, camera.name, end, self.config.clip.segment_format.extension())
   }
 
-  fn getOldestLocalClip(&self) -> Result<PathBuf, Box<dyn Error>> {
-    let mut entries: Vec<fs::DirEntry> = fs::read_dir(self.clipsPath.clone()).unwrap().filter_map(Result::ok).collect();
-    entries = entries.into_iter().filter(|e| e.file_name() != ".gitkeep").collect();
+  fn removeBoundaryCuts(&self, camera: &CameraInput) {
+    for end in ["start", "end"] {
+      let _ = fs::remove_file(self.boundaryCutPath(camera, end)); //only there for frame accurate clips
+    }
+  }
+
+  /// Re-encodes the part of a segment inside the clip window, so the clip can start or end between keyframes
+  async fn cutBoundary(&self, camera: &CameraInput, segmentPath: &str, end: &str, inpoint: Option<f64>, outpoint: Option<f64>) -> Result<String, Box<dyn Error>> {
+    let cutPath = self.boundaryCutPath(camera, end);
+    buildBoundaryPipeline(segmentPath, inpoint, outpoint, &camera.encoding, &cutPath).run().await
+      .map_err(|e| format!("Boundary cut failed: {}", e))?;
+    Ok(cutPath)
+  }
+
+  /// sha256 is None when the clip still has to be hashed
+  async fn catalogClip(&self, camera: &CameraInput, trigger: &ClipTrigger, result: &ClipResult, previews: Option<ClipPreviews>, sha256: Option<String>) -> Result<(), Box<dyn Error>> {
+    let clipPath = PathBuf::from(&result.path);
+    let sha256 = match sha256 {
+      Some(sha256) => sha256,
+      None         => tokio::task::spawn_blocking(move || sha256File(&clipPath).map_err(|e| e.to_string())).await??,
+    };
+    ClipCatalog::global().insert(ClipRecord {
+      fileName       : result.fileName.clone(),
+      camera         : Some(camera.name.clone()),
+      triggerSource  : trigger.source,
+      triggerTime    : trigger.time,
+      motionPeakScore: trigger.motionPeakScore,
+      durationSec    : result.durationSec,
+      sizeB          : result.sizeB,
+      sha256         : sha256,
+      uploadState    : UploadState::Pending,
+      driveFileId    : None,
+      locked         : false,
+      createdAt      : Utc::now(),
+      posterFileName : previews.as_ref().map(|p| p.posterFileName.clone()),
+      previewFileName: previews.map(|p| p.previewFileName),
+    })
+  }
+
+  /// Writes the concat list of completed segments that overlap the window, trimmed to the window
+  /// with inpoint/outpoint or re-encoded at either end when frame accurate, and returns the size
+  /// and number of those segments
+  async fn makePathsForWritingFileAndGetOutputSize(&self, camera: &CameraInput, ring: &SegmentRing, recordingPathsFilePath: &str, windowStart: DateTime<Utc>, windowEnd: DateTime<Utc>, frameAccurate: bool) -> Result<(i64, usize), Box<dyn Error>> {
+    let segments: Vec<Segment> = ring.completedBetween(windowStart, windowEnd);
+    if segments.is_empty() {
+      return Err("No recorded segments cover the clip window".into());
+    }
+
+    let outputSize: u64 = segments
+      .iter()
+      .map(|s| s.sizeB)
+      .sum();
+
+    let lastIndex = segments.len() - 1;
+    let mut pathsForWriting: Vec<String> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+      let path = ring.path(segment).display().to_string(); //absolute as the recording may be on another drive
+      let inpoint = match i == 0 && windowStart > segment.startTime {
+        true  => Some(secondsBetween(segment.startTime, windowStart)), //timestamps restart at 0 in every segment
+        false => None,
+      };
+      let outpoint = match i == lastIndex && windowEnd < segment.endTime() {
+        true  => Some(secondsBetween(segment.startTime, windowEnd)),
+        false => None,
+      };
+      let entry = match frameAccurate && (inpoint.is_some() || outpoint.is_some()) {
+        true  => concatEntry(&self.cutBoundary(camera, &path, if i == 0 { "start" } else { "end" }, inpoint, outpoint).await?, None, None),
+        false => concatEntry(&path, inpoint, outpoint), //stream copy starts on the keyframe before inpoint
+      };
+      pathsForWriting.push(entry);
+    }
+
+    fs::write(recordingPathsFilePath, pathsForWriting.join("\n"))?;
 
-    if entries.is_empty() { panic!("Clips folder is empty!")}
-    entries.sort_by_key({|e|
-      e.metadata().unwrap()
-        .modified().unwrap()
-    });
-    let oldest = entries.first().unwrap().path();
-    Ok(oldest)
+    Ok((outputSize as i64, segments.len()))
   }
 }
 
 pub async fn startCameraAndStream() -> Result<(), Box<dyn Error>> {
   let config = Config::getConfig().await?;
-  let liveRecordingPath = env::current_dir()?.parent().unwrap().join("lib/zerocam/LiveRecording/").display().to_string();
-
-  fs::remove_dir_all(&liveRecordingPath)?;
-  fs::create_dir_all(&liveRecordingPath)?; //wipe recordings from previous session to prevent corruption
+  prepareLiveRecording(&config)?;
 
   let mediamtxPath             = env::current_dir()?.parent().unwrap().join("lib/zerocam/MediaMTX/mediamtx").display().to_string();
   let mediamtxLocalConfPath    = env::current_dir()?.parent().unwrap().join("lib/zerocam/MediaMTX/mediamtx-local.yml").display().to_string();
   let mediamtxInternetConfPath = env::current_dir()?.parent().unwrap().join("lib/zerocam/MediaMTX/mediamtx-internet.yml").display().to_string();
 
-  Command::new(&mediamtxPath) //start localhost stream
-    .stdout(Stdio::null()) //peace
-    .stderr(Stdio::null()) //and quiet :)
-    .arg(mediamtxLocalConfPath)
-    .spawn()?;
-
-  Command::new(&mediamtxPath) //start internet stream with security features
-    .stdout(Stdio::null()) //peace
-    .stderr(Stdio::null()) //and quiet :)
-    .arg(mediamtxInternetConfPath)
-    .spawn()?;
-
-  Command::new("sudo")
-    .stdout(Stdio::null())//peace
-    .stderr(Stdio::null())//and quiet :)
-    .arg("modprobe")
-    .arg("v4l2loopback")
-    .arg("devices=1") //create virtual devices for loopback
-    .spawn()?;
-
-  Command::new("ffmpeg")
-    .stdout(Stdio::null()) //peace
-    .stderr(Stdio::null()) //and quiet :))
-    .arg("-threads")       .arg("4"                            )
-    .arg("-f")             .arg("v4l2"                         ) // demuxer format v4l2
-    .arg("-input_format")  .arg("mjpeg"                        )
-    .arg("-framerate")     .arg(config.camera_input.fps        )
-    .arg("-video_size")    .arg(&config.camera_input.resolution)
-
-    //input
-    .arg("-i")             .arg("/dev/video0") // read original source
-
-    //output for storage
-    .arg("-vf")              .arg("format=gray"                             )
-    .arg("-pix_fmt")         .arg("yuv420p"                                 )
-    .arg("-c:v")             .arg(&config.camera_input.encoder              )
-    .arg("-b:v")             .arg("20M"                                     ) //bitrate
-    .arg("-f")               .arg("segment"                                 ) //output in segments
-    .arg("-force_key_frames").arg(format!("expr:gte(t,n_forced*{})"
-                                , config.camera_input.clip.segment_size_sec)) //force key frames every x seconds for splitting
-    .arg("-reset_timestamps").arg("1"                                       ) //prevent corruption of timestamps when loop recording
-    .arg("-segment_time")    .arg(config.camera_input.clip.segment_size_sec ) //x seconds per segment
-    .arg("-segment_wrap")    .arg(config.camera_input.clip.segments         ) //loop after x segments
-    .arg(format!("{}output%03d.ts", liveRecordingPath)                      ) //output in numbered files
-
-    //Output for local stream to Internet
-    .arg("-f")        .arg("rtsp"                                  ) // RTSP container
-    .arg("-pix_fmt")  .arg("yuv420p"                               )
-    .arg("-c:v")      .arg("libx264"                               ) // h.264 encoder with gpu
-    .arg("-preset")   .arg("ultrafast"                             ) // Keep latency low
-    .arg("-s")        .arg(config.internet_stream_output.resolution)
-    .arg("-b:v")      .arg(config.internet_stream_output.bit_rate  )
-    .arg("-r")        .arg(config.internet_stream_output.fps       )
-    .arg("rtsp://localhost:8555/stream1"                           ) // RTMP stream to local MediaMTX
-
-    //Output for local stream to GUI
-    .arg("-f")          .arg("rtsp"                             ) // RTSP container
-    .arg("-pix_fmt")    .arg("yuv420p"                          )
-    .arg("-c:v")        .arg("libx264"                          ) // h.264 encoder
-    .arg("-preset")     .arg("ultrafast"                        ) // Keep latency low
-    .arg("-s")          .arg(config.gui_stream_output.resolution)
-    .arg("-b:v")        .arg(config.gui_stream_output.bit_rate  )
-    .arg("-r")          .arg(config.gui_stream_output.fps       )
-    .arg("rtsp://localhost:8554/stream1"                        ) // RTMP stream to local MediaMTX
-
-    //output for opencv
-    .arg("-pix_fmt")       .arg("yuv420p"                        )
-    .arg("-s")             .arg(config.motion_listener.resolution)
-    .arg("-r")             .arg(config.motion_listener.fps       )
-    .arg("-b:v")           .arg(&config.motion_listener.bit_rate )
-    .arg("-f").arg("v4l2") .arg("/dev/video2"                    )
-    .spawn()?;
+  let supervisor = Supervisor::global();
+  supervisor.supervise(ChildSpec { //start localhost stream
+    name   : "mediamtx-local".to_string(),
+    program: mediamtxPath.clone(),
+    args   : vec![mediamtxLocalConfPath],
+    env    : Vec::new(),
+    stdout : None,
+    restart: true,
+  });
+
+  supervisor.supervise(ChildSpec { //start internet stream with security features
+    name   : "mediamtx-internet".to_string(),
+    program: mediamtxPath,
+    args   : vec![mediamtxInternetConfPath],
+    env    : Vec::new(),
+    stdout : None,
+    restart: true,
+  });
+
+  //muting covers every microphone, so any camera starting muted starts them all muted
+  AudioSource::setMutedState(config.camera_input.iter().any(|c| c.audio.as_ref().map(|a| a.muted).unwrap_or(false)));
+  for camera in &config.camera_input {
+    info!("Reading camera {} input from {}", camera.name, camera.source.describe());
+    if let Some(audio) = &camera.audio {
+      info!("Reading camera {} audio input from {}", camera.name, audio.source.describe());
+    }
+    supervisor.supervise(recordingSpec(&config, camera)?);
+  }
+
+  let mut storageEvents = StorageRoot::global().subscribe();
+  tokio::spawn(async move {
+    while !matches!(storageEvents.recv().await, Err(RecvError::Closed)) {
+      let restarted: Result<Vec<ChildSpec>, String> = prepareLiveRecording(&config)
+        .and_then(|_| config.camera_input.iter().map(|camera| recordingSpec(&config, camera)).collect())
+        .map_err(|e| e.to_string());
+      match restarted {
+        Ok(specs) => {
+          for spec in specs {
+            Supervisor::global().replace(spec).await;
+          }
+          info!("Recording moved to {}", StorageRoot::global().liveRecordingPath().display());
+        }
+        Err(e) => error!("Failed to move recording to the new storage root: {}", e),
+      }
+    }
+  });
   Ok(())
 }
+
+/// Restarts the recording with the microphone swapped for silence or back, the SegmentRing
+/// picks up the new segment list so the buffer before the toggle can still be clipped. The whole
+/// ffmpeg of each camera with audio restarts, so recording and both live streams pause briefly
+pub async fn setAudioMuted(muted: bool) -> Result<(), Box<dyn Error>> {
+  let config = Config::getConfig().await?;
+  let specs: Vec<ChildSpec> = config.camera_input.iter()
+    .filter(|camera| camera.audio.is_some())
+    .map(|camera| recordingSpec(&config, camera))
+    .collect::<Result<_, _>>()?;
+  if specs.is_empty() {
+    return Err("No audio input is configured".into());
+  }
+  AudioSource::setMutedState(muted);
+  for spec in specs {
+    Supervisor::global().replace(spec).await;
+  }
+  Ok(())
+}
+
+/// Wipes recordings from a previous session or drive to prevent corruption and points each
+/// camera's SegmentRing at its own directory in LiveRecording on the current storage root
+fn prepareLiveRecording(config: &ConfigFile) -> Result<(), Box<dyn Error>> {
+  let liveRecordingPath = StorageRoot::global().liveRecordingPath();
+  if liveRecordingPath.exists() {
+    fs::remove_dir_all(&liveRecordingPath)?;
+  }
+  for camera in &config.camera_input {
+    let cameraRecordingPath = liveRecordingPath.join(&camera.name);
+    fs::create_dir_all(&cameraRecordingPath)?;
+    SegmentRing::forCamera(&camera.name).follow(&camera.name, &cameraRecordingPath, config.clip.segments.parse()?)?;
+  }
+  Ok(())
+}
+
+fn recordingSpec(config: &ConfigFile, camera: &CameraInput) -> Result<ChildSpec, Box<dyn Error>> {
+  let liveRecordingPath = format!("{}/", StorageRoot::global().liveRecordingPath().join(&camera.name).display());
+  Ok(ChildSpec {
+    name   : format!("ffmpeg-{}", camera.name),
+    program: "ffmpeg".to_string(),
+    args   : buildCameraPipeline(config, camera, &liveRecordingPath).render()?,
+    env    : config.overlay.environment(), //drawtext formats localtime in TZ
+    stdout : Some(MotionFeed::forCamera(&camera.name, &config.motion_listener.feedResolution())?.stdoutReader(&camera.name)),
+    restart: true,
+  })
+}
+
+/// Checks the clip ffmpeg wrote is really there and playable before anyone reports success
+async fn verifyClip(path: &str, segmentCount: usize) -> Result<ClipResult, Box<dyn Error>> {
+  let sizeB = fs::metadata(path).map_err(|e| format!("Clip {} was not written: {}", path, e))?.len();
+  if sizeB == 0 {
+    return Err(format!("Clip {} is empty", path).into());
+  }
+
+  let probe = FfProbe::probe(path).await?;
+  if !probe.streams.iter().any(|s| s == "video") {
+    return Err(format!("Clip {} has no video stream", path).into());
+  }
+  if probe.durationSec <= 0.0 {
+    return Err(format!("Clip {} has no duration", path).into());
+  }
+
+  Ok(ClipResult {
+    path        : path.to_string(),
+    fileName    : Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string(),
+    sizeB       : sizeB,
+    durationSec : probe.durationSec,
+    segmentCount: segmentCount,
+    streams     : probe.streams,
+    posterPath  : None,
+    previewPath : None,
+  })
+}
+
+/// One file of a concat demuxer list, trimmed to inpoint..outpoint seconds into the file
+fn concatEntry(path: &str, inpoint: Option<f64>, outpoint: Option<f64>) -> String {
+  let mut entry = "file '".to_owned() + path + "'";
+  if let Some(inpoint) = inpoint {
+    entry += &format!("\ninpoint {:.3}", inpoint);
+  }
+  if let Some(outpoint) = outpoint {
+    entry += &format!("\noutpoint {:.3}", outpoint);
+  }
+  entry
+}
+
+fn secondsBetween(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+  (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+/// The recording pipeline for one camera, its input split into storage segments, both streams and
+/// its motion listener feed, the optional audio input only goes into storage
+pub fn buildCameraPipeline(config: &ConfigFile, camera: &CameraInput, liveRecordingPath: &str) -> FfmpegPipeline {
+  let overlay = &config.overlay;
+  let segmentFormat = config.clip.segment_format;
+  let storageResolution        = camera.orientation.resolution(&camera.resolution);
+  let internetStreamResolution = config.internet_stream_output.orientation.resolution(&config.internet_stream_output.resolution);
+  let guiStreamResolution      = config.gui_stream_output.orientation.resolution(&config.gui_stream_output.resolution);
+  let storage = encodedOutput(FfmpegOutput::new("storage", format!("{}output%03d.{}", liveRecordingPath, segmentFormat.extension())) //output in numbered files
+    , &camera.encoding, &camera.orientation, overlay.storage, overlay)
+    .map("0:v");
+  let storage = match &camera.audio {
+    Some(audio) => storage
+      .map("1:a")
+      .option("-c:a", &audio.codec      )
+      .option("-b:a", &audio.bit_rate   )
+      .option("-ar" , &audio.sample_rate)
+      .option("-ac" , "1"               ), //mono so the track matches the silence used when muted
+    None => storage,
+  };
+  let storage = segmentFormat.apply(storage
+    .option("-s"                , &storageResolution           ) //sources other than v4l2 may not match
+    .option("-r"                , &camera.fps                  )
+    .format("segment"                                          )) //output in segments of the configured container
+    .option("-force_key_frames" , format!("expr:gte(t,n_forced*{})"
+                                , config.clip.segment_size_sec )) //force key frames every x seconds for splitting
+    .option("-reset_timestamps" , "1"                          ) //prevent corruption of timestamps when loop recording
+    .option("-segment_time"     , &config.clip.segment_size_sec) //x seconds per segment
+    .option("-segment_wrap"     , &config.clip.segments        ) //loop after x segments
+    .option("-segment_list"     , format!("{}{}", liveRecordingPath
+                                , SEGMENT_LIST_FILE_NAME       )) //SegmentRing indexes finished segments from this
+    .option("-segment_list_type", "csv"                        )
+    .option("-segment_list_size", "0"                          ); //append every segment instead of rewriting the list
+
+  let internetStream = encodedOutput(FfmpegOutput::new("internet_stream", format!("rtsp://localhost:8555/{}", camera.name)) // RTSP stream to local MediaMTX, a path per camera
+    , &config.internet_stream_output.encoding, &config.internet_stream_output.orientation, overlay.internet_stream, overlay)
+    .map("0:v"                                                 )
+    .format("rtsp"                                             ) // RTSP container
+    .option("-s"      , &internetStreamResolution                )
+    .option("-r"      , &config.internet_stream_output.fps       );
+
+  let guiStream = encodedOutput(FfmpegOutput::new("gui_stream", format!("rtsp://localhost:8554/{}", camera.name)) // RTSP stream to local MediaMTX
+    , &config.gui_stream_output.encoding, &config.gui_stream_output.orientation, overlay.gui_stream, overlay)
+    .map("0:v"                                            )
+    .format("rtsp"                                        ) // RTSP container
+    .option("-s"      , &guiStreamResolution                )
+    .option("-r"      , &config.gui_stream_output.fps       );
+
+  let motionFeed = config.motion_listener.orientation.apply(FfmpegOutput::new("motion_feed", "pipe:1")) //stdout, read by MotionFeed
+    .map("0:v"                                           )
+    .option("-pix_fmt", "gray"                           ) //one byte per pixel, the listener only compares brightness
+    .option("-s"      , config.motion_listener.feedResolution())
+    .option("-r"      , &config.motion_listener.fps       )
+    .format("rawvideo");
+
+  let pipeline = FfmpegPipeline::new()
+    .globalOption("-loglevel", "warning") //supervisor forwards stderr into our log
+    .globalFlag("-nostats")
+    .globalOption("-threads", "4")
+    .input(camera.source.input(&camera.resolution, &camera.fps));
+  let pipeline = match &camera.audio {
+    Some(audio) => pipeline.input(audioInput(audio)),
+    None        => pipeline,
+  };
+  pipeline
+    .output(storage)
+    .output(internetStream)
+    .output(guiStream)
+    .output(motionFeed)
+}
+
+/// Colour, orientation, overlay then encoder, the overlay goes after rotating so it reads upright
+fn encodedOutput(output: FfmpegOutput, encoding: &Encoding, orientation: &Orientation, overlayEnabled: bool, overlay: &Overlay) -> FfmpegOutput {
+  let output = orientation.apply(encoding.applyColour(output));
+  let output = match overlayEnabled {
+    true  => output.filter(overlay.drawtextFilter()),
+    false => output,
+  };
+  encoding.applyEncoder(output)
+}
+
+/// Re-encodes inpoint..outpoint seconds of one segment with the camera's encoder so it can be
+/// concatenated with the copied segments around it, audio is copied as every packet is a keyframe
+pub fn buildBoundaryPipeline(segmentPath: &str, inpoint: Option<f64>, outpoint: Option<f64>, encoding: &Encoding, cutPath: &str) -> FfmpegPipeline {
+  let input = FfmpegInput::new(segmentPath);
+  let input = match inpoint {
+    Some(inpoint) => input.option("-ss", format!("{:.3}", inpoint)), //decodes up to the exact frame
+    None          => input,
+  };
+  let input = match outpoint {
+    Some(outpoint) => input.option("-to", format!("{:.3}", outpoint)),
+    None           => input,
+  };
+  FfmpegPipeline::new()
+    .globalFlag("-y") //the cut from the previous clip is overwritten
+    .input(input)
+    .output(encoding.applyEncoder(FfmpegOutput::new("boundary", cutPath)
+      .map("0"))
+      .option("-c:a", "copy"))
+}
+
+/// Encodes the jpeg frames in framesDirectory into a video, frames are named by capture time so
+/// the glob sorts them in order
+pub fn buildTimelapsePipeline(framesDirectory: &str, fps: u32, encoding: &Encoding, clipFormat: ClipFormat, clipPath: &str) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .input(FfmpegInput::new(format!("{}/*.jpg", framesDirectory))
+      .option("-framerate"   , fps.to_string())
+      .option("-pattern_type", "glob"))
+    .output(clipFormat.apply(encoding.applyEncoder(FfmpegOutput::new("timelapse", clipPath))))
+}
+
+/// Joins the files listed in recordingPathsFile into one clip without re-encoding
+pub fn buildConcatPipeline(recordingPathsFilePath: &str, clipPath: &str, clipFormat: ClipFormat) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .input(FfmpegInput::new(recordingPathsFilePath) //input list of files to be concatenated
+      .format("concat"   ) //input existing files
+      .option("-safe", "0")) //disables safety to allow full path use
+    .output(clipFormat.apply(FfmpegOutput::new("clip", clipPath)
+      .option("-c", "copy")))
+}
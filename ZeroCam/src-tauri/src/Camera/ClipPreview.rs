@@ -0,0 +1,76 @@
+use crate::Camera::FfmpegPipeline::{FfmpegInput, FfmpegOutput, FfmpegPipeline};
+use crate::Storage::StorageRoot::StorageRoot;
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+const PREVIEW_LENGTH_SEC: f64 = 4.0;
+const POSTER_WIDTH      : u32 = 640;
+const PREVIEW_WIDTH     : u32 = 320;
+const PREVIEW_FPS       : u32 = 5;
+
+/// Small stand ins for a clip so telegram, the GUI and drive don't need the full video
+#[derive(Debug, Clone)]
+pub struct ClipPreviews {
+  pub posterFileName : String, // jpeg frame at the trigger
+  pub previewFileName: String, // short animated gif around the trigger
+}
+
+pub fn posterFileName(clipFileName: &str) -> String {
+  format!("{}.jpg", clipFileName)
+}
+
+pub fn previewFileName(clipFileName: &str) -> String {
+  format!("{}.gif", clipFileName)
+}
+
+pub fn previewPath(fileName: &str) -> PathBuf {
+  StorageRoot::global().previewsPath().join(fileName)
+}
+
+/// Writes the poster and animated preview for a clip into the Previews directory, eventSec is
+/// where the trigger falls within the clip
+pub async fn generate(clipPath: &str, clipFileName: &str, eventSec: f64) -> Result<ClipPreviews, Box<dyn Error>> {
+  let previews = ClipPreviews {
+    posterFileName : posterFileName(clipFileName),
+    previewFileName: previewFileName(clipFileName),
+  };
+  let posterPath  = previewPath(&previews.posterFileName).display().to_string();
+  let previewPath = previewPath(&previews.previewFileName).display().to_string();
+
+  buildPosterPipeline(clipPath, &posterPath, eventSec).run().await.map_err(|e| format!("Poster failed: {}", e))?;
+  buildPreviewPipeline(clipPath, &previewPath, (eventSec - PREVIEW_LENGTH_SEC / 2.0).max(0.0)).run().await
+    .map_err(|e| format!("Preview failed: {}", e))?;
+  Ok(previews)
+}
+
+/// Deletes whatever previews exist for a clip
+pub fn remove(clipFileName: &str) {
+  let _ = fs::remove_file(previewPath(&posterFileName(clipFileName)));
+  let _ = fs::remove_file(previewPath(&previewFileName(clipFileName)));
+}
+
+pub fn buildPosterPipeline(clipPath: &str, posterPath: &str, atSec: f64) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .globalFlag("-y")
+    .input(FfmpegInput::new(clipPath)
+      .option("-ss", format!("{:.3}", atSec))) //seek before decoding
+    .output(FfmpegOutput::new("poster", posterPath)
+      .filter(format!("scale={}:-2", POSTER_WIDTH))
+      .option("-frames:v", "1")
+      .jpegQuality(4))
+}
+
+pub fn buildPreviewPipeline(clipPath: &str, previewPath: &str, fromSec: f64) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .globalFlag("-y")
+    .input(FfmpegInput::new(clipPath)
+      .option("-ss", format!("{:.3}", fromSec))
+      .option("-t" , format!("{:.3}", PREVIEW_LENGTH_SEC)))
+    .output(FfmpegOutput::new("preview", previewPath)
+      .filter(format!("fps={}", PREVIEW_FPS))
+      .filter(format!("scale={}:-2:flags=lanczos", PREVIEW_WIDTH))
+      .filter("split[a][b];[a]palettegen[p];[b][p]paletteuse") //a per clip palette keeps the gif small
+      .option("-loop", "0"))
+}
@@ -1,29 +1,151 @@
 use crate::Camera::CameraController;
+use crate::Camera::CameraController::ClipResult;
 use crate::Config::getConfig;
 use crate::Config::ConfigFile;
 
-use chrono::Utc;
+use chrono::{DateTime, TimeDelta, Utc};
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerSource {
+  Motion,
+  Gui,
+  Telegram,
+  Api,
+  Archive,   // continuous recording, one clip per archive period
+  Timelapse, // one frame every few seconds while parked, one clip per day
+  Unknown, // clips found on disk that were made before the catalog
+}
+
+/// Exact time range cut from the recording buffer instead of the configured window around a trigger
+#[derive(Debug, Clone, Copy)]
+pub struct ClipRange {
+  pub from: DateTime<Utc>,
+  pub to  : DateTime<Utc>,
+}
+
+/// What asked for a clip, the moment the clip window is centred on and which cameras to clip
+#[derive(Debug, Clone)]
+pub struct ClipTrigger {
+  pub source         : TriggerSource,
+  pub time           : DateTime<Utc>,
+  pub motionPeakScore: Option<f64>,       // largest frame difference while the motion trigger built up
+  pub camera         : Option<String>,    // every camera when missing
+  pub range          : Option<ClipRange>, // the window pre/post event seconds around time when missing
+  pub frameAccurate  : bool,              // re-encode the first and last segment instead of cutting on keyframes
+}
+
+impl ClipTrigger {
+  pub fn now(source: TriggerSource) -> Self {
+    Self {
+      source         : source,
+      time           : Utc::now(),
+      motionPeakScore: None,
+      camera         : None,
+      range          : None,
+      frameAccurate  : false,
+    }
+  }
+
+  pub fn motion(peakScore: f64) -> Self {
+    Self {
+      source         : TriggerSource::Motion,
+      time           : Utc::now(),
+      motionPeakScore: Some(peakScore),
+      camera         : None,
+      range          : None,
+      frameAccurate  : false,
+    }
+  }
+
+  /// Trigger times from other clocks (telegram servers, the GUI) are never allowed in the future
+  pub fn at(source: TriggerSource, time: DateTime<Utc>) -> Self {
+    Self {
+      source         : source,
+      time           : time.min(Utc::now()),
+      motionPeakScore: None,
+      camera         : None,
+      range          : None,
+      frameAccurate  : false,
+    }
+  }
+
+  /// Exports from..to out of the recording buffer, the end can't be in the future either
+  pub fn export(source: TriggerSource, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+    let to = to.min(Utc::now());
+    Self {
+      source         : source,
+      time           : to,
+      motionPeakScore: None,
+      camera         : None,
+      range          : Some(ClipRange { from: from, to: to }),
+      frameAccurate  : false,
+    }
+  }
+
+  /// Exports the last seconds of the recording buffer
+  pub fn exportLast(source: TriggerSource, seconds: u32) -> Self {
+    let to = Utc::now();
+    Self::export(source, to - TimeDelta::seconds(seconds as i64), to)
+  }
+
+  /// A finished archive period, named and catalogued by when it started
+  pub fn archive(from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+    Self {
+      time: from,
+      ..Self::export(TriggerSource::Archive, from, to)
+    }
+  }
+
+  /// A day of parked timelapse frames, named and catalogued by the first frame
+  pub fn timelapse(from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+    Self {
+      time: from,
+      ..Self::export(TriggerSource::Timelapse, from, to)
+    }
+  }
+
+  /// Starts and ends the clip on the exact frame, only the segments at either end are re-encoded
+  pub fn frameAccurate(mut self, frameAccurate: bool) -> Self {
+    self.frameAccurate = frameAccurate;
+    self
+  }
+
+  /// Only clips the named camera instead of all of them
+  pub fn forCamera(mut self, camera: impl Into<String>) -> Self {
+    self.camera = Some(camera.into());
+    self
+  }
+}
 
 pub struct ClipScheduler {
   cameraController: CameraController::CameraController,
   config          : ConfigFile,
 }
 
-static IS_RUNNING: AtomicBool = AtomicBool::new(false);
-static LAST_RUN: AtomicI64 = AtomicI64::new(0);
+static RUNNING : Mutex<BTreeSet<String>>      = Mutex::new(BTreeSet::new());  // cameras with a clip in progress
+static LAST_RUN: Mutex<BTreeMap<String, i64>> = Mutex::new(BTreeMap::new()); // unix seconds of each camera's last clip
 
-struct RunningGuard;
+/// The cameras a trigger claimed, released when the clip is done or fails
+struct RunningGuard {
+  cameras: Vec<String>,
+}
 
 impl Drop for RunningGuard {
   fn drop(&mut self) {
-    IS_RUNNING.store(false, Ordering::SeqCst);
+    let mut running = RUNNING.lock().unwrap();
+    for camera in &self.cameras {
+      running.remove(camera);
+    }
   }
 }
 
-/// This allows only 1 process to happen at any time and prevents any concurrent attempts
+/// One clip at a time per camera, so a trigger on one camera is not lost while another is clipping
 impl ClipScheduler {
   pub async fn new() -> Self {
     Self {
@@ -32,29 +154,52 @@ impl ClipScheduler {
     }
   }
 
-  pub async fn scheduleClip(&self) -> Result<(), Box<dyn Error>> {
-    let currentTime = Utc::now().timestamp();
-    let lastRunDifference = currentTime - LAST_RUN.load(Ordering::SeqCst);
-    if(lastRunDifference < self.config.camera_input.clip.cooldown_sec) {
-      let remainingCooldown = self.config.camera_input.clip.cooldown_sec - lastRunDifference;
-      warn!("Clip scheduler on cooldown, {}sec  remaining - skipping this request", &remainingCooldown);
-      return Err(format!("Clip scheduler on cooldown, {}sec  remaining - skipping this request", self.config.camera_input.clip.cooldown_sec - &remainingCooldown).into());
+  /// One clip per camera the trigger covers, cameras already clipping or on cooldown are skipped
+  pub async fn scheduleClip(&self, trigger: ClipTrigger) -> Result<Vec<ClipResult>, Box<dyn Error>> {
+    let cameras: Vec<String> = match &trigger.camera {
+      Some(name) => vec![name.clone()],
+      None       => self.config.camera_input.iter().map(|c| c.name.clone()).collect(),
+    };
+    let (guard, skipped) = self.claim(&cameras);
+    for reason in &skipped {
+      warn!("{} - skipping this request", reason);
     }
-
-    if IS_RUNNING
-      .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-      .is_err()
-    {
-      warn!("Clip process in progress - skipping this request");
-      return Err("Clip process in progress - skipping this request".into());
+    if guard.cameras.is_empty() {
+      return Err(format!("{} - skipping this request", skipped.join(", ")).into());
     }
 
-    let _guard = RunningGuard;
+    info!("Clip triggered by {:?} at {} for {}", trigger.source, trigger.time, guard.cameras.join(", "));
+    let results = self.cameraController.clip(&trigger, &guard.cameras).await?;
 
-    let result = self.cameraController.clip().await?;
+    let finishedAt = Utc::now().timestamp();
+    let mut lastRun = LAST_RUN.lock().unwrap();
+    for camera in &guard.cameras {
+      lastRun.insert(camera.clone(), finishedAt);
+    }
+    drop(lastRun);
+    for result in &results {
+      info!("Clip completed successfully: {}", result.fileName);
+    }
+    Ok(results)
+  }
 
-    LAST_RUN.store(Utc::now().timestamp(), Ordering::SeqCst);
-    info!("Clip completed successfully");
-    Ok(result)
+  /// Takes the cameras that are neither clipping nor cooling down, with why the others were left out
+  fn claim(&self, cameras: &[String]) -> (RunningGuard, Vec<String>) {
+    let now = Utc::now().timestamp();
+    let lastRun = LAST_RUN.lock().unwrap();
+    let mut running = RUNNING.lock().unwrap();
+    let mut claimed: Vec<String> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    for camera in cameras {
+      let remainingCooldown = self.config.clip.cooldown_sec - (now - lastRun.get(camera).copied().unwrap_or(0));
+      if remainingCooldown > 0 {
+        skipped.push(format!("Camera {} on cooldown, {}sec remaining", camera, remainingCooldown));
+      } else if !running.insert(camera.clone()) {
+        skipped.push(format!("Camera {} clip in progress", camera));
+      } else {
+        claimed.push(camera.clone());
+      }
+    }
+    (RunningGuard { cameras: claimed }, skipped)
   }
 }
@@ -0,0 +1,56 @@
+use crate::Camera::FfmpegPipeline::FfmpegOutput;
+use crate::Config::{ClipFormat, SegmentFormat};
+
+use mime_guess::{from_path, Mime};
+
+const FRAGMENTED_MOVFLAGS: &str = "+frag_keyframe+empty_moov+default_base_moof"; // every keyframe starts a fragment that plays on its own
+
+impl SegmentFormat {
+  pub fn extension(&self) -> &'static str {
+    match self {
+      SegmentFormat::Mpegts => "ts",
+      SegmentFormat::Fmp4   => "mp4",
+      SegmentFormat::Mkv    => "mkv",
+    }
+  }
+
+  /// Muxer for each file the segment muxer writes
+  pub fn apply(&self, output: FfmpegOutput) -> FfmpegOutput {
+    match self {
+      SegmentFormat::Mpegts => output.option("-segment_format", "mpegts"),
+      SegmentFormat::Fmp4   => output
+        .option("-segment_format"        , "mp4")
+        .option("-segment_format_options", format!("movflags={}", FRAGMENTED_MOVFLAGS)),
+      SegmentFormat::Mkv    => output.option("-segment_format", "matroska"),
+    }
+  }
+}
+
+impl ClipFormat {
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ClipFormat::Mp4 | ClipFormat::Fmp4 => "mp4",
+      ClipFormat::Mkv                    => "mkv",
+    }
+  }
+
+  /// Clips are written under a temporary name, so the muxer is named rather than guessed from it
+  pub fn apply(&self, output: FfmpegOutput) -> FfmpegOutput {
+    match self {
+      ClipFormat::Mp4  => output.format("mp4").option("-movflags", "+faststart"), //index moved to the front once the clip is complete
+      ClipFormat::Fmp4 => output.format("mp4").option("-movflags", FRAGMENTED_MOVFLAGS),
+      ClipFormat::Mkv  => output.format("matroska"),
+    }
+  }
+}
+
+/// Content type of a clip or segment from its extension
+pub fn mimeType(fileName: &str) -> Mime {
+  let known = match fileName.rsplit('.').next().unwrap_or_default() {
+    "mp4" => "video/mp4",
+    "mkv" => "video/x-matroska",
+    "ts"  => "video/mp2t",
+    _     => return from_path(fileName).first_or_octet_stream(),
+  };
+  known.parse().unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM)
+}
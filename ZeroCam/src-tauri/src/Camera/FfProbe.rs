@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::process::Stdio;
+use tokio::process::Command;
+
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+  pub durationSec: f64,
+  pub streams    : Vec<String>, // codec type of each stream eg. video, audio
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+  format : ProbeFormat,
+  #[serde(default)]
+  streams: Vec<ProbeStream>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+  duration: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+  codec_type: String,
+}
+
+/// Reads the container duration and stream types of a finished media file
+pub async fn probe(path: &str) -> Result<ProbeResult, Box<dyn Error>> {
+  let output = Command::new("ffprobe")
+    .stdin(Stdio::null())
+    .arg("-v")            .arg("error"                            )
+    .arg("-show_entries") .arg("format=duration:stream=codec_type")
+    .arg("-of")           .arg("json"                             )
+    .arg(path)
+    .output()
+    .await?;
+
+  if !output.status.success() {
+    return Err(format!("ffprobe failed on {}: {}", path, String::from_utf8_lossy(&output.stderr).trim()).into());
+  }
+
+  let parsed: ProbeOutput = serde_json::from_slice(&output.stdout)?;
+  Ok(ProbeResult {
+    durationSec: parsed.format.duration.and_then(|d| d.parse().ok()).unwrap_or(0.0),
+    streams    : parsed.streams.into_iter().map(|s| s.codec_type).collect(),
+  })
+}
@@ -0,0 +1,414 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Options that change how video or audio is encoded, these cannot be combined with copying that stream
+const VIDEO_ENCODING_OPTIONS: [&str; 6] = ["-b:v", "-preset", "-pix_fmt", "-s", "-r", "-force_key_frames"];
+const AUDIO_ENCODING_OPTIONS: [&str; 2] = ["-b:a", "-ar"];
+
+/// Why a pipeline run by `FfmpegPipeline::run` did not finish
+#[derive(Debug)]
+pub enum FfmpegError {
+  Invalid(String),   // the pipeline did not validate
+  Spawn(io::Error),  // ffmpeg could not be started
+  Failed { status: ExitStatus, stderr: String },
+}
+
+impl fmt::Display for FfmpegError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FfmpegError::Invalid(reason)         => write!(f, "invalid ffmpeg pipeline: {}", reason),
+      FfmpegError::Spawn(e)                => write!(f, "failed to start ffmpeg: {}", e),
+      FfmpegError::Failed { status, stderr } => write!(f, "ffmpeg exited with {}: {}", status, stderr),
+    }
+  }
+}
+
+impl Error for FfmpegError {}
+
+/// A single `-i` input and the options that come before it
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegInput {
+  options: Vec<(String, Option<String>)>,
+  source : String,
+}
+
+/// A named output, its stream maps, filter chain and options that come before the output target
+#[derive(Debug, Clone)]
+pub struct FfmpegOutput {
+  name   : String,
+  maps   : Vec<String>, // eg. 0:v, empty lets ffmpeg pick one stream of each type
+  filters: Vec<String>,
+  options: Vec<(String, Option<String>)>,
+  target : String,
+}
+
+/// Model of a whole ffmpeg invocation that renders to the same argv every time and refuses to
+/// render when options conflict, so pipeline changes can be checked without running ffmpeg
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegPipeline {
+  globalOptions: Vec<(String, Option<String>)>,
+  inputs       : Vec<FfmpegInput>,
+  outputs      : Vec<FfmpegOutput>,
+}
+
+impl FfmpegInput {
+  pub fn new(source: impl Into<String>) -> Self {
+    Self {
+      options: Vec::new(),
+      source : source.into(),
+    }
+  }
+
+  pub fn option(mut self, key: &str, value: impl Into<String>) -> Self {
+    self.options.push((key.to_string(), Some(value.into())));
+    self
+  }
+
+  pub fn flag(mut self, key: &str) -> Self {
+    self.options.push((key.to_string(), None));
+    self
+  }
+
+  pub fn format(self, format: &str) -> Self {
+    self.option("-f", format)
+  }
+}
+
+impl FfmpegOutput {
+  pub fn new(name: &str, target: impl Into<String>) -> Self {
+    Self {
+      name   : name.to_string(),
+      maps   : Vec::new(),
+      filters: Vec::new(),
+      options: Vec::new(),
+      target : target.into(),
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// adds a `-map` so the output only takes the streams it is given, the one option that can repeat
+  pub fn map(mut self, stream: impl Into<String>) -> Self {
+    self.maps.push(stream.into());
+    self
+  }
+
+  /// appends a filter to this output's `-vf` chain
+  pub fn filter(mut self, filter: impl Into<String>) -> Self {
+    self.filters.push(filter.into());
+    self
+  }
+
+  pub fn option(mut self, key: &str, value: impl Into<String>) -> Self {
+    self.options.push((key.to_string(), Some(value.into())));
+    self
+  }
+
+  pub fn flag(mut self, key: &str) -> Self {
+    self.options.push((key.to_string(), None));
+    self
+  }
+
+  pub fn format(self, format: &str) -> Self {
+    self.option("-f", format)
+  }
+
+  /// For jpeg frames, 2 is the best and 31 the worst
+  pub fn jpegQuality(self, quality: u8) -> Self {
+    self.option("-q:v", quality.to_string())
+  }
+
+  /// Whether the stream type is copied, by `-c copy` or its own eg. `-c:v copy`
+  fn copies(&self, codecOption: &str) -> bool {
+    self.options.iter().any(|(key, value)| {
+      (key == "-c" || key == codecOption) && value.as_deref() == Some("copy")
+    })
+  }
+
+  fn validate(&self) -> Result<(), Box<dyn Error>> {
+    if self.target.is_empty() {
+      return Err(format!("Output {} has no target", self.name).into());
+    }
+    validateUniqueOptions(&self.options, &format!("output {}", self.name))?;
+    if self.options.iter().any(|(key, _)| key == "-vf" || key == "-filter:v") {
+      return Err(format!("Output {} sets -vf directly, add filters with filter() instead", self.name).into());
+    }
+    if self.options.iter().any(|(key, _)| key == "-map") {
+      return Err(format!("Output {} sets -map directly, add streams with map() instead", self.name).into());
+    }
+    if self.copies("-c:v") {
+      if !self.filters.is_empty() {
+        return Err(format!("Output {} copies the video stream so it cannot apply filters", self.name).into());
+      }
+      if let Some((key, _)) = self.options.iter().find(|(key, _)| VIDEO_ENCODING_OPTIONS.contains(&key.as_str())) {
+        return Err(format!("Output {} copies the video stream so it cannot set {}", self.name, key).into());
+      }
+    }
+    if self.copies("-c:a") {
+      if let Some((key, _)) = self.options.iter().find(|(key, _)| AUDIO_ENCODING_OPTIONS.contains(&key.as_str())) {
+        return Err(format!("Output {} copies the audio stream so it cannot set {}", self.name, key).into());
+      }
+    }
+    Ok(())
+  }
+}
+
+impl FfmpegPipeline {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn globalOption(mut self, key: &str, value: impl Into<String>) -> Self {
+    self.globalOptions.push((key.to_string(), Some(value.into())));
+    self
+  }
+
+  pub fn globalFlag(mut self, key: &str) -> Self {
+    self.globalOptions.push((key.to_string(), None));
+    self
+  }
+
+  pub fn input(mut self, input: FfmpegInput) -> Self {
+    self.inputs.push(input);
+    self
+  }
+
+  pub fn output(mut self, output: FfmpegOutput) -> Self {
+    self.outputs.push(output);
+    self
+  }
+
+  pub fn outputs(&self) -> &Vec<FfmpegOutput> {
+    &self.outputs
+  }
+
+  pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+    if self.inputs.is_empty() {
+      return Err("Pipeline has no inputs".into());
+    }
+    if self.outputs.is_empty() {
+      return Err("Pipeline has no outputs".into());
+    }
+    validateUniqueOptions(&self.globalOptions, "global options")?;
+    for input in &self.inputs {
+      if input.source.is_empty() {
+        return Err("Pipeline input has no source".into());
+      }
+      validateUniqueOptions(&input.options, &format!("input {}", input.source))?;
+    }
+
+    let mut names: HashSet<&str> = HashSet::new();
+    for output in &self.outputs {
+      if !names.insert(&output.name) {
+        return Err(format!("Output name {} is used more than once", output.name).into());
+      }
+      output.validate()?;
+      for map in &output.maps {
+        let input: usize = map.split(':').next().unwrap_or_default().parse()
+          .map_err(|_| format!("Output {} maps {} which does not name an input", output.name, map))?;
+        if input >= self.inputs.len() {
+          return Err(format!("Output {} maps {} but there are only {} inputs", output.name, map, self.inputs.len()).into());
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// global options, then each input, then each output in the order they were added
+  pub fn render(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    self.validate()?;
+
+    let mut argv: Vec<String> = Vec::new();
+    pushOptions(&mut argv, &self.globalOptions);
+    for input in &self.inputs {
+      pushOptions(&mut argv, &input.options);
+      argv.push("-i".to_string());
+      argv.push(input.source.clone());
+    }
+    for output in &self.outputs {
+      for map in &output.maps {
+        argv.push("-map".to_string());
+        argv.push(map.clone());
+      }
+      if !output.filters.is_empty() {
+        argv.push("-vf".to_string());
+        argv.push(output.filters.join(","));
+      }
+      pushOptions(&mut argv, &output.options);
+      argv.push(output.target.clone());
+    }
+    Ok(argv)
+  }
+
+  pub fn command(&self) -> Result<Command, Box<dyn Error>> {
+    let mut command = Command::new("ffmpeg");
+    command.args(self.render()?);
+    Ok(command)
+  }
+
+  /// Runs ffmpeg to completion for one shot jobs like clips and frames, its stderr only comes
+  /// back in the error. ffmpeg is killed if the returned future is dropped, eg. on a timeout
+  pub async fn run(&self) -> Result<(), FfmpegError> {
+    let command = self.command().map_err(|e| FfmpegError::Invalid(e.to_string()))?;
+    let output = tokio::process::Command::from(command)
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .kill_on_drop(true)
+      .output()
+      .await
+      .map_err(FfmpegError::Spawn)?;
+    if !output.status.success() {
+      return Err(FfmpegError::Failed {
+        status: output.status,
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+      });
+    }
+    Ok(())
+  }
+}
+
+fn validateUniqueOptions(options: &Vec<(String, Option<String>)>, owner: &str) -> Result<(), Box<dyn Error>> {
+  let mut seen: HashSet<&str> = HashSet::new();
+  for (key, _) in options {
+    if !seen.insert(key) {
+      return Err(format!("Option {} is set more than once on {}", key, owner).into());
+    }
+  }
+  Ok(())
+}
+
+fn pushOptions(argv: &mut Vec<String>, options: &Vec<(String, Option<String>)>) {
+  for (key, value) in options {
+    argv.push(key.clone());
+    if let Some(value) = value {
+      argv.push(value.clone());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Camera::CameraController::{buildCameraPipeline, buildConcatPipeline};
+  use crate::Config::{testConfig, ClipFormat};
+
+  fn args(argv: &[&str]) -> Vec<String> {
+    argv.iter().map(|a| a.to_string()).collect()
+  }
+
+  #[test]
+  fn rendersGlobalsThenInputsThenOutputs() {
+    let pipeline = FfmpegPipeline::new()
+      .globalFlag("-y")
+      .input(FfmpegInput::new("a.ts").option("-ss", "1"))
+      .input(FfmpegInput::new("b.ts").format("mpegts"))
+      .output(FfmpegOutput::new("first", "out.mp4").map("1:v").map("0:a").filter("scale=640:360").filter("format=gray").option("-c:v", "libx264"))
+      .output(FfmpegOutput::new("second", "out.jpg").option("-frames:v", "1"));
+
+    assert_eq!(pipeline.render().unwrap(), args(&[
+      "-y",
+      "-ss", "1", "-i", "a.ts",
+      "-f", "mpegts", "-i", "b.ts",
+      "-map", "1:v", "-map", "0:a", "-vf", "scale=640:360,format=gray", "-c:v", "libx264", "out.mp4",
+      "-frames:v", "1", "out.jpg",
+    ]));
+  }
+
+  #[test]
+  fn rejectsDuplicateOptions() {
+    let output = FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "out.mp4").option("-r", "10").option("-r", "15"));
+    assert!(output.render().is_err());
+
+    let input = FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts").format("mpegts").format("mp4"))
+      .output(FfmpegOutput::new("clip", "out.mp4"));
+    assert!(input.render().is_err());
+
+    let global = FfmpegPipeline::new()
+      .globalFlag("-y")
+      .globalFlag("-y")
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "out.mp4"));
+    assert!(global.render().is_err());
+
+    let names = FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "a.mp4"))
+      .output(FfmpegOutput::new("clip", "b.mp4"));
+    assert!(names.render().is_err());
+  }
+
+  #[test]
+  fn rejectsEncodingACopiedStream() {
+    for copy in ["-c", "-c:v"] {
+      let filtered = FfmpegPipeline::new()
+        .input(FfmpegInput::new("a.ts"))
+        .output(FfmpegOutput::new("clip", "out.mp4").option(copy, "copy").filter("format=gray"));
+      assert!(filtered.render().is_err(), "{} copy with a filter", copy);
+
+      let encoded = FfmpegPipeline::new()
+        .input(FfmpegInput::new("a.ts"))
+        .output(FfmpegOutput::new("clip", "out.mp4").option(copy, "copy").option("-b:v", "1M"));
+      assert!(encoded.render().is_err(), "{} copy with a bitrate", copy);
+    }
+
+    let audioCopy = FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "out.mp4").option("-c:a", "copy").filter("format=gray").option("-b:v", "1M"));
+    assert!(audioCopy.render().is_ok());
+
+    let audioEncoded = FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "out.mp4").option("-c:v", "copy").option("-b:a", "64k"));
+    assert!(audioEncoded.render().is_ok());
+  }
+
+  #[test]
+  fn rejectsMapsOfMissingInputs() {
+    let pipeline = |map: &str| FfmpegPipeline::new()
+      .input(FfmpegInput::new("a.ts"))
+      .output(FfmpegOutput::new("clip", "out.mp4").map(map));
+
+    assert!(pipeline("0:v").render().is_ok());
+    assert!(pipeline("1:v").render().is_err());
+    assert!(pipeline("v").render().is_err());
+  }
+
+  #[test]
+  fn rendersCameraPipelineForTestConfig() {
+    let config = testConfig();
+    let pipeline = buildCameraPipeline(&config, &config.camera_input[0], "/x/LiveRecording/front/");
+    let argv = pipeline.render().unwrap();
+
+    let names: Vec<&str> = pipeline.outputs().iter().map(|o| o.name()).collect();
+    assert_eq!(names, ["storage", "internet_stream", "gui_stream", "motion_feed"]);
+    assert_eq!(argv[..5], args(&["-loglevel", "warning", "-nostats", "-threads", "4"]));
+    assert_eq!(argv[5..15], args(&["-f", "v4l2", "-input_format", "mjpeg", "-framerate", "15", "-video_size", "1920x1080", "-i", "/dev/video0"]));
+
+    let target = |t: &str| argv.iter().position(|a| a == t).unwrap();
+    assert!(target("/x/LiveRecording/front/output%03d.ts") < target("rtsp://localhost:8555/front"));
+    assert!(target("rtsp://localhost:8555/front") < target("rtsp://localhost:8554/front"));
+    assert_eq!(argv[argv.len() - 11..], args(&["-map", "0:v", "-pix_fmt", "gray", "-s", "640x360", "-r", "10", "-f", "rawvideo", "pipe:1"]));
+    assert!(argv.windows(2).any(|w| w == ["-segment_wrap", "5"]));
+  }
+
+  #[test]
+  fn rendersConcatPipeline() {
+    assert_eq!(buildConcatPipeline("/x/list.txt", "/x/.c.mp4.part", ClipFormat::Mp4).render().unwrap(), args(&[
+      "-f", "concat", "-safe", "0", "-i", "/x/list.txt",
+      "-c", "copy", "-f", "mp4", "-movflags", "+faststart", "/x/.c.mp4.part",
+    ]));
+    assert_eq!(buildConcatPipeline("/x/list.txt", "/x/.c.mkv.part", ClipFormat::Mkv).render().unwrap(), args(&[
+      "-f", "concat", "-safe", "0", "-i", "/x/list.txt",
+      "-c", "copy", "-f", "matroska", "/x/.c.mkv.part",
+    ]));
+  }
+}
@@ -0,0 +1,139 @@
+use crate::Config::{MotionAlgorithm, MotionListener as MotionConfig};
+
+use opencv::{
+  core::{absdiff, magnitude, split, sum_elems, Point, Ptr, Rect, Size, Vector, CV_8U},
+  imgproc::{bounding_rect, create_clahe, find_contours, gaussian_blur, threshold, CHAIN_APPROX_SIMPLE, RETR_EXTERNAL, ThresholdTypes::THRESH_BINARY},
+  prelude::*,
+  video::{calc_optical_flow_farneback, create_background_subtractor_knn, create_background_subtractor_mog2, BackgroundSubtractorKNN, BackgroundSubtractorMOG2},
+};
+use serde::Serialize;
+use std::error::Error;
+
+const BACKGROUND_HISTORY: i32 = 500;   // frames the background model remembers
+const MIN_REGION_AREA_PX: i32 = 25;    // smaller blobs are sensor noise
+
+/// Where something moved in the motion feed, in its pixels
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Region {
+  pub x     : i32,
+  pub y     : i32,
+  pub width : i32,
+  pub height: i32,
+}
+
+pub struct Detection {
+  pub score  : f64, // sum of the motion mask, 255 per moving pixel, compared with threshold_sum_kilo
+  pub regions: Vec<Region>,
+}
+
+/// Scores one gray frame of the motion feed against the frames before it, the first frame only
+/// primes the detector and scores 0
+pub trait MotionDetector: Send {
+  fn detect(&mut self, frame: &Mat) -> Result<Detection, Box<dyn Error>>;
+}
+
+/// The detector picked by motion_listener.algorithm
+pub fn newDetector(config: &MotionConfig) -> Result<Box<dyn MotionDetector>, Box<dyn Error>> {
+  Ok(match config.algorithm {
+    MotionAlgorithm::FrameDiff   => Box::new(FrameDiff { sensitivityInverse: config.sensitivity_inverse, previous: None }),
+    MotionAlgorithm::Mog2        => Box::new(BackgroundSubtraction::<BackgroundSubtractorMOG2> {
+      subtractor: create_background_subtractor_mog2(BACKGROUND_HISTORY, 16., false)?, //no shadow detection, shadows count as motion
+      primed    : false,
+    }),
+    MotionAlgorithm::Knn         => Box::new(BackgroundSubtraction::<BackgroundSubtractorKNN> {
+      subtractor: create_background_subtractor_knn(BACKGROUND_HISTORY, 400., false)?,
+      primed    : false,
+    }),
+    MotionAlgorithm::OpticalFlow => Box::new(OpticalFlow { thresholdPx: config.flow_threshold_px, previous: None }),
+  })
+}
+
+/// Blurred then contrast equalised difference between consecutive frames
+struct FrameDiff {
+  sensitivityInverse: f64,
+  previous          : Option<Mat>,
+}
+
+impl MotionDetector for FrameDiff {
+  fn detect(&mut self, frame: &Mat) -> Result<Detection, Box<dyn Error>> {
+    let mut frameBlurred = Mat::default();
+    gaussian_blur(frame, &mut frameBlurred, Size::new(15, 15), 0., 0., 0.into())?;
+
+    let mut claheImg = Mat::default();
+    create_clahe(15f64, Size::new(1, 1))?.apply(&frameBlurred, &mut claheImg)?;
+
+    let Some(previous) = self.previous.replace(claheImg.clone()) else {
+      return Ok(Detection { score: 0.0, regions: Vec::new() });
+    };
+    let mut difference = Mat::default();
+    absdiff(&claheImg, &previous, &mut difference)?;
+
+    let mut differenceBinned = Mat::default();
+    threshold(&difference, &mut differenceBinned, self.sensitivityInverse, 255f64, THRESH_BINARY.into())?;
+    maskDetection(&differenceBinned)
+  }
+}
+
+/// Pixels that don't fit a learned background, slowly repeating motion becomes part of it
+struct BackgroundSubtraction<T> {
+  subtractor: Ptr<T>,
+  primed    : bool,
+}
+
+impl<T> MotionDetector for BackgroundSubtraction<T> where Ptr<T>: BackgroundSubtractorTrait + Send {
+  fn detect(&mut self, frame: &Mat) -> Result<Detection, Box<dyn Error>> {
+    let mut frameBlurred = Mat::default();
+    gaussian_blur(frame, &mut frameBlurred, Size::new(5, 5), 0., 0., 0.into())?;
+
+    let mut foreground = Mat::default();
+    self.subtractor.apply(&frameBlurred, &mut foreground, -1.)?; //automatic learning rate
+    if !self.primed {
+      self.primed = true;
+      return Ok(Detection { score: 0.0, regions: Vec::new() }); //all foreground until there is a background
+    }
+    maskDetection(&foreground)
+  }
+}
+
+/// Dense Farneback flow between consecutive frames, a pixel moves when its flow is over thresholdPx
+struct OpticalFlow {
+  thresholdPx: f64,
+  previous   : Option<Mat>,
+}
+
+impl MotionDetector for OpticalFlow {
+  fn detect(&mut self, frame: &Mat) -> Result<Detection, Box<dyn Error>> {
+    let Some(previous) = self.previous.replace(frame.try_clone()?) else {
+      return Ok(Detection { score: 0.0, regions: Vec::new() });
+    };
+    let mut flow = Mat::default();
+    calc_optical_flow_farneback(&previous, frame, &mut flow, 0.5, 3, 15, 3, 5, 1.2, 0)?;
+
+    let mut components: Vector<Mat> = Vector::new();
+    split(&flow, &mut components)?;
+    let mut flowMagnitude = Mat::default();
+    magnitude(&components.get(0)?, &components.get(1)?, &mut flowMagnitude)?;
+
+    let mut moving = Mat::default();
+    threshold(&flowMagnitude, &mut moving, self.thresholdPx, 255f64, THRESH_BINARY.into())?;
+    let mut mask = Mat::default();
+    moving.convert_to(&mut mask, CV_8U, 1., 0.)?; //float flow, contours need 8 bit
+    maskDetection(&mask)
+  }
+}
+
+/// Scores a 0 or 255 motion mask and boxes its blobs
+fn maskDetection(mask: &Mat) -> Result<Detection, Box<dyn Error>> {
+  let score: f64 = sum_elems(mask)?.iter().sum();
+
+  let mut contours: Vector<Vector<Point>> = Vector::new();
+  find_contours(mask, &mut contours, RETR_EXTERNAL, CHAIN_APPROX_SIMPLE, Point::new(0, 0))?;
+  let mut regions: Vec<Region> = Vec::new();
+  for contour in contours.iter() {
+    let Rect { x, y, width, height } = bounding_rect(&contour)?;
+    if width * height >= MIN_REGION_AREA_PX {
+      regions.push(Region { x, y, width, height });
+    }
+  }
+  Ok(Detection { score, regions })
+}
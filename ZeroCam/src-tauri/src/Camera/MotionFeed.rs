@@ -0,0 +1,98 @@
+use crate::Process::Supervisor::StdoutReader;
+
+use log::info;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::ChildStdout;
+use tokio::sync::watch;
+
+static MOTION_FEEDS: OnceLock<Mutex<BTreeMap<String, Arc<MotionFeed>>>> = OnceLock::new();
+
+/// One gray byte per pixel, row by row
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub sequence: u64, // increases with every frame so a listener can tell it has seen one before
+  pub pixels  : Vec<u8>,
+}
+
+/// Low resolution gray frames one camera's ffmpeg writes as rawvideo to its stdout, only the
+/// newest frame is kept so a slow motion listener can never back up the recording pipeline
+pub struct MotionFeed {
+  width : usize,
+  height: usize,
+  latest: watch::Sender<Option<Frame>>, // listeners await the next frame without holding a thread
+}
+
+impl MotionFeed {
+  /// The feed for a camera, created the first time it is asked for, resolution is eg. 640x360
+  pub fn forCamera(camera: &str, resolution: &str) -> Result<Arc<MotionFeed>, Box<dyn Error>> {
+    let (width, height) = parseResolution(resolution)?;
+    let mut feeds = MOTION_FEEDS.get_or_init(|| Mutex::new(BTreeMap::new())).lock().unwrap();
+    let feed = feeds.entry(camera.to_string()).or_insert_with(|| Arc::new(MotionFeed {
+      width : width,
+      height: height,
+      latest: watch::Sender::new(None),
+    }));
+    if (feed.width, feed.height) != (width, height) {
+      return Err(format!("Motion feed for camera {} is already {}x{}", camera, feed.width, feed.height).into());
+    }
+    Ok(feed.clone())
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  /// Hands ffmpeg's stdout to this feed every time the supervisor starts it
+  pub fn stdoutReader(self: &Arc<Self>, camera: &str) -> StdoutReader {
+    let feed = self.clone();
+    let camera = camera.to_string();
+    StdoutReader(Arc::new(move |stdout| {
+      let feed = feed.clone();
+      let camera = camera.clone();
+      tokio::spawn(async move {
+        feed.read(stdout).await;
+        info!("Motion feed for camera {} ended", camera);
+      });
+    }))
+  }
+
+  /// The newest frame's sequence, 0 before the first frame
+  pub fn sequence(&self) -> u64 {
+    self.latest.borrow().as_ref().map(|f| f.sequence).unwrap_or(0)
+  }
+
+  /// Waits for a frame newer than sequence, None if ffmpeg sent nothing in time
+  pub async fn waitForFrame(&self, after: u64, timeout: Duration) -> Option<Frame> {
+    let mut latest = self.latest.subscribe();
+    let newer = latest.wait_for(|f| f.as_ref().map(|f| f.sequence > after).unwrap_or(false));
+    let frame = match tokio::time::timeout(timeout, newer).await {
+      Ok(Ok(frame)) => frame.clone(),
+      _             => None,
+    };
+    frame
+  }
+
+  /// Reads whole frames until ffmpeg exits, always draining the pipe however slow the listener is
+  async fn read(&self, mut stdout: ChildStdout) {
+    let mut pixels = vec![0u8; self.width * self.height];
+    while stdout.read_exact(&mut pixels).await.is_ok() {
+      self.latest.send_modify(|latest| {
+        let sequence = latest.as_ref().map(|f| f.sequence + 1).unwrap_or(1);
+        *latest = Some(Frame { sequence: sequence, pixels: pixels.clone() });
+      });
+    }
+  }
+}
+
+fn parseResolution(resolution: &str) -> Result<(usize, usize), Box<dyn Error>> {
+  let (width, height) = resolution.split_once('x').ok_or(format!("Resolution {} is not WIDTHxHEIGHT", resolution))?;
+  Ok((width.trim().parse()?, height.trim().parse()?))
+}
@@ -1,104 +1,110 @@
-use crate::Camera::ClipScheduler::ClipScheduler;
+use crate::Camera::ClipScheduler::{ClipScheduler, ClipTrigger};
+use crate::Camera::MotionDetector::{newDetector, MotionDetector};
+use crate::Camera::MotionFeed::MotionFeed;
 use crate::Config;
 use crate::Config::ConfigFile;
 
 use log::{debug, info, warn};
-use opencv::{
-  core::{absdiff, sum_elems, Size},
-  highgui::wait_key,
-  imgproc::{
-    cvt_color, equalize_hist, gaussian_blur, threshold, ColorConversionCodes::COLOR_BGR2GRAY,
-    ThresholdTypes::THRESH_BINARY,
-  },
-  prelude::*,
-  videoio::{VideoCapture, CAP_ANY, CAP_PROP_BUFFERSIZE},
-};
+use opencv::prelude::*;
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, AtomicI8, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
-use thread::sleep;
-use opencv::imgproc::create_clahe;
+use tokio::time::sleep;
 
-static WATCHING : AtomicBool = AtomicBool::new(true);
-static TRIGGERED: AtomicBool = AtomicBool::new(false);
-static DURATION : AtomicI8   = AtomicI8::new(0);
+static WATCHING: AtomicBool = AtomicBool::new(true); // parked state is shared by every camera
 
+const FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Watches one camera's motion feed and clips that camera when something moves
 pub struct MotionListener {
   clipScheduler: Arc<ClipScheduler>,
-  config       : ConfigFile
+  config       : ConfigFile,
+  camera       : String,
 }
 
 impl MotionListener {
-  pub async fn new(clipScheduler: Arc<ClipScheduler>) -> Result<MotionListener, Box<dyn Error>> {
+  pub async fn new(clipScheduler: Arc<ClipScheduler>, camera: String) -> Result<MotionListener, Box<dyn Error>> {
+    let config = Config::getConfig().await?;
+    if config.camera(&camera).is_none() {
+      return Err(format!("No camera is named {}", camera).into());
+    }
     Ok(Self{
       clipScheduler: clipScheduler,
-      config       : Config::getConfig().await?
+      config       : config,
+      camera       : camera,
     })
   }
 
   pub async fn run(self) {
-
-    let mut cap = VideoCapture::new(2, CAP_ANY).unwrap();
-    cap.set(CAP_PROP_BUFFERSIZE, 1.0).unwrap();
-
-    let mut startFrame = Mat::default();
-    cap.read(&mut startFrame).unwrap();
-
-    let mut startFrameGray = Mat::default();
-    cvt_color(&mut startFrame, &mut startFrameGray, COLOR_BGR2GRAY.into(),0).unwrap();
-
-    let mut frame = Mat::default();
+    let feed = MotionFeed::forCamera(&self.camera, &self.config.motion_listener.feedResolution()).unwrap();
+    let mut lastSequence: u64 = 0;
+    let mut detector: Box<dyn MotionDetector> = newDetector(&self.config.motion_listener).unwrap();
+    info!("Motion sensor {}: {:?}", self.camera, self.config.motion_listener.algorithm);
+
+    let mut peakDifference: f64 = 0.0; //reported with the clip so it can be ranked later
+    let mut triggered = false;
+    let mut duration: i8 = 0;
     loop{
       if WATCHING.load(Ordering::SeqCst) {
-        sleep(Duration::from_millis(self.config.motion_listener.frame_delay_millisec));
-        cap.read(&mut frame).expect("error reading");
-
-        let mut frameGray = Mat::default();
-        cvt_color(&mut frame, &mut frameGray, COLOR_BGR2GRAY.into(),0).unwrap();
-
-        let mut frameBlurred = Mat::default();
-        gaussian_blur(&mut frameGray, &mut frameBlurred, Size::new(15, 15), 0., 0., 0.into()).expect("error bluring");
-
-        let mut claheImg = Mat::default();
-        let clahe = create_clahe(15f64, Size::new(1, 1));
-        clahe.expect("error creating clahe").apply(&mut frameBlurred, &mut  claheImg).expect("error appyling clahe");
-
-        let mut difference = Mat::default();
-        absdiff(&mut claheImg, &mut startFrameGray, &mut difference).expect("error getting difference");
-
-        let mut differenceBinned = Mat::default();
-        threshold(&mut difference, &mut differenceBinned, self.config.motion_listener.sensitivity_inverse, 255f64, THRESH_BINARY.into()).expect("error binning difference");
-
-        let differenceTotal: f64 = sum_elems(&mut differenceBinned.clone()).expect("error summing elems").iter().sum();
-        debug!("Difference Total: {}", differenceTotal);
-
-        startFrameGray = claheImg;
+        sleep(Duration::from_millis(self.config.motion_listener.frame_delay_millisec)).await;
+        let frameGray = self.nextFrame(&feed, &mut lastSequence).await; //ffmpeg already made it gray
+
+        let detection = match detector.detect(&frameGray).map_err(|e| e.to_string()) {
+          Ok(detection) => detection,
+          Err(e)        => {
+            warn!("Motion sensor {}: detection failed: {}", self.camera, e);
+            continue;
+          }
+        };
+        let differenceTotal: f64 = detection.score;
+        debug!("Camera {} Difference Total: {} in {} regions", self.camera, differenceTotal, detection.regions.len());
 
         if differenceTotal < (self.config.motion_listener.threshold_sum_kilo * 1000.0){
-          if DURATION.load(Ordering::Relaxed) > 0{
-            DURATION.store(DURATION.load(Ordering::Relaxed) - 1, Ordering::Relaxed);
-            if TRIGGERED.load(Ordering::Relaxed){
-              TRIGGERED.store(false, Ordering::Relaxed);
-              debug!("Motion sensor: RELAXED");
+          if duration > 0{
+            duration -= 1;
+            if duration == 0 { peakDifference = 0.0; }
+            if triggered{
+              triggered = false;
+              debug!("Motion sensor {}: RELAXED", self.camera);
             }
           }
-        }else if !TRIGGERED.load(Ordering::Relaxed){
-          if DURATION.load(Ordering::Relaxed) < self.config.motion_listener.trigger_duration{
-            DURATION.store(DURATION.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        }else if !triggered{
+          peakDifference = peakDifference.max(differenceTotal);
+          if duration < self.config.motion_listener.trigger_duration{
+            duration += 1;
           }
-          if DURATION.load(Ordering::Relaxed) == self.config.motion_listener.trigger_duration{
-            if !TRIGGERED.load(Ordering::Relaxed){
-              TRIGGERED.store(true, Ordering::Relaxed);
-              info!("Motion sensor: TRIGGERED");
-              if self.clipScheduler.scheduleClip().await.is_err(){warn!{"Motion sensor clip cooldown overlap!"}}
+          if duration == self.config.motion_listener.trigger_duration{
+            if !triggered{
+              triggered = true;
+              info!("Motion sensor {}: TRIGGERED at {:?}", self.camera, detection.regions);
+              let trigger = ClipTrigger::motion(peakDifference).forCamera(&self.camera);
+              peakDifference = 0.0;
+              match self.clipScheduler.scheduleClip(trigger).await {
+                Ok(clips) => for clip in clips { info!("Motion sensor clip saved: {}", clip.fileName) },
+                Err(e)    => warn!("Motion sensor {} clip failed: {}", self.camera, e),
+              }
             }
           }
         }
-        debug!("Motion Duration: {}", DURATION.load(Ordering::Relaxed));
+        debug!("Camera {} Motion Duration: {}", self.camera, duration);
       }else{
-        sleep(Duration::from_secs(5));
+        sleep(Duration::from_secs(5)).await;
+      }
+    }
+  }
+
+  /// Waits for a frame newer than lastSequence, ffmpeg may be restarting so this keeps waiting
+  async fn nextFrame(&self, feed: &MotionFeed, lastSequence: &mut u64) -> Mat {
+    loop {
+      match feed.waitForFrame(*lastSequence, FRAME_TIMEOUT).await {
+        Some(frame) => {
+          *lastSequence = frame.sequence;
+          return Mat::new_rows_cols_with_data(feed.height() as i32, feed.width() as i32, &frame.pixels)
+            .and_then(|m| m.try_clone())
+            .expect("error reading frame");
+        }
+        None => warn!("Motion sensor {}: no frames for {}sec, is ffmpeg running?", self.camera, FRAME_TIMEOUT.as_secs()),
       }
     }
   }
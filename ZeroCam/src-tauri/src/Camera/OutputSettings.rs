@@ -0,0 +1,88 @@
+use crate::Camera::FfmpegPipeline::FfmpegOutput;
+use crate::Config::{Encoding, MotionListener, Orientation};
+
+impl Encoding {
+  /// grayscale goes first in the filter chain so later filters work on fewer channels
+  pub fn applyColour(&self, output: FfmpegOutput) -> FfmpegOutput {
+    match self.grayscale {
+      true  => output.filter("format=gray"),
+      false => output,
+    }
+  }
+
+  pub fn applyEncoder(&self, output: FfmpegOutput) -> FfmpegOutput {
+    let output = output
+      .option("-pix_fmt", &self.pix_fmt )
+      .option("-c:v"    , &self.codec   )
+      .option("-b:v"    , &self.bit_rate);
+    match self.preset.trim() {
+      ""     => output,
+      preset => output.option("-preset", preset),
+    }
+  }
+}
+
+impl Orientation {
+  pub fn apply(&self, output: FfmpegOutput) -> FfmpegOutput {
+    let mut output = match self.rotate % 360 {
+      90  => output.filter("transpose=clock"),
+      180 => output.filter("hflip").filter("vflip"),
+      270 => output.filter("transpose=cclock"),
+      _   => output,
+    };
+    if self.hflip { output = output.filter("hflip"); }
+    if self.vflip { output = output.filter("vflip"); }
+    output
+  }
+
+  /// The WxH an output ends up with, a quarter turn swaps the sides of the configured resolution
+  pub fn resolution(&self, resolution: &str) -> String {
+    match (self.rotate % 360, resolution.split_once('x')) {
+      (90 | 270, Some((width, height))) => format!("{}x{}", height, width),
+      _                                 => resolution.to_string(),
+    }
+  }
+}
+
+impl MotionListener {
+  /// Size of the frames in the motion feed, shared by its ffmpeg output and MotionFeed
+  pub fn feedResolution(&self) -> String {
+    self.orientation.resolution(&self.resolution)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Camera::FfmpegPipeline::{FfmpegInput, FfmpegPipeline};
+
+  fn orientation(rotate: u16, hflip: bool, vflip: bool) -> Orientation {
+    Orientation { rotate: rotate, hflip: hflip, vflip: vflip }
+  }
+
+  /// The rendered output arguments after the input
+  fn outputArgs(orientation: &Orientation) -> Vec<String> {
+    let argv = FfmpegPipeline::new()
+      .input(FfmpegInput::new("in.ts"))
+      .output(orientation.apply(FfmpegOutput::new("out", "out.ts")))
+      .render()
+      .unwrap();
+    argv[2..].to_vec()
+  }
+
+  #[test]
+  fn rotatesThenFlips() {
+    assert_eq!(outputArgs(&orientation(0, false, false)), ["out.ts"]);
+    assert_eq!(outputArgs(&orientation(90, false, false)), ["-vf", "transpose=clock", "out.ts"]);
+    assert_eq!(outputArgs(&orientation(180, false, false)), ["-vf", "hflip,vflip", "out.ts"]);
+    assert_eq!(outputArgs(&orientation(270, true, false)), ["-vf", "transpose=cclock,hflip", "out.ts"]);
+    assert_eq!(outputArgs(&orientation(450, false, true)), ["-vf", "transpose=clock,vflip", "out.ts"]);
+  }
+  #[test]
+  fn swapsTheResolutionOnAQuarterTurn() {
+    assert_eq!(orientation(0, true, true).resolution("1920x1080"), "1920x1080");
+    assert_eq!(orientation(90, false, false).resolution("1920x1080"), "1080x1920");
+    assert_eq!(orientation(180, false, false).resolution("1920x1080"), "1920x1080");
+    assert_eq!(orientation(270, false, false).resolution("640x360"), "360x640");
+  }
+}
@@ -0,0 +1,96 @@
+use crate::Config::Overlay;
+
+impl Overlay {
+  /// drawtext filter burning the device label and wall clock into the frame, the time is
+  /// formatted in the ffmpeg process's TZ so the timezone is applied through the environment
+  pub fn drawtextFilter(&self) -> String {
+    let clock = format!("%{{localtime:{}}}", escape(&self.time_format, &['\\', ':']));
+    let text = match self.device_label.trim() {
+      ""    => clock,
+      label => format!("{}  {}", escape(label, &['\\', '%', '{', '}']), clock),
+    };
+
+    let mut options = vec![
+      format!("text={}", escape(&text, &['\\', ':', '\''])),
+      format!("fontsize={}", self.font_size),
+      "fontcolor=white".to_string(),
+      "box=1".to_string(),
+      "boxcolor=black@0.5".to_string(),
+      "boxborderw=6".to_string(),
+      "x=10".to_string(),
+      "y=h-th-10".to_string(),
+    ];
+    if !self.font_file.trim().is_empty() {
+      options.push(format!("fontfile={}", escape(self.font_file.trim(), &['\\', ':', '\''])));
+    }
+    //ffmpeg unescapes the filter graph, then the filter options, then the text expansion
+    format!("drawtext={}", escape(&options.join(":"), &['\\', '\'', '[', ']', ',', ';']))
+  }
+
+  /// environment for the ffmpeg process, empty keeps the system timezone
+  pub fn environment(&self) -> Vec<(String, String)> {
+    match self.timezone.trim() {
+      ""       => Vec::new(),
+      timezone => vec![("TZ".to_string(), timezone.to_string())],
+    }
+  }
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if special.contains(&c) { escaped.push('\\'); }
+    escaped.push(c);
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn overlay(label: &str, timeFormat: &str) -> Overlay {
+    Overlay {
+      device_label   : label.to_string(),
+      timezone       : String::new(),
+      time_format    : timeFormat.to_string(),
+      font_size      : 32,
+      font_file      : String::new(),
+      storage        : true,
+      internet_stream: true,
+      gui_stream     : false,
+    }
+  }
+
+  #[test]
+  fn escapesTheClockThroughEveryLevel() {
+    assert_eq!(overlay("ZeroCam", "%H:%M").drawtextFilter(),
+      r"drawtext=text=ZeroCam  %{localtime\\:%H\\\\\\:%M}:fontsize=32:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=6:x=10:y=h-th-10");
+  }
+
+  #[test]
+  fn escapesTheLabelSoItIsNotExpandedOrSplit() {
+    let filter = overlay("Van's [rear], 50%", "%H").drawtextFilter();
+    assert!(filter.starts_with(r"drawtext=text=Van\\\'s \[rear\]\, 50\\\\%  %{localtime\\:%H}:fontsize=32"), "{}", filter);
+  }
+
+  #[test]
+  fn leavesOutAnEmptyLabel() {
+    assert!(overlay("  ", "%H").drawtextFilter().starts_with(r"drawtext=text=%{localtime\\:%H}:"));
+  }
+
+  #[test]
+  fn escapesTheFontFile() {
+    let mut overlay = overlay("ZeroCam", "%H");
+    overlay.font_file = r"C:\fonts\mono.ttf".to_string();
+    assert!(overlay.drawtextFilter().ends_with(r":fontfile=C\\:\\\\fonts\\\\mono.ttf"), "{}", overlay.drawtextFilter());
+  }
+
+  #[test]
+  fn setsTheTimezoneOnlyWhenConfigured() {
+    let mut overlay = overlay("ZeroCam", "%H");
+    assert!(overlay.environment().is_empty());
+    overlay.timezone = "Europe/London".to_string();
+    assert_eq!(overlay.environment(), [("TZ".to_string(), "Europe/London".to_string())]);
+  }
+}
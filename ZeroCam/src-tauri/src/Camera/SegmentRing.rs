@@ -0,0 +1,341 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+pub const SEGMENT_LIST_FILE_NAME: &str = "segments.csv";
+
+static SEGMENT_RINGS: OnceLock<Mutex<BTreeMap<String, Arc<SegmentRing>>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SegmentState {
+  InProgress,
+  Completed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+  pub index      : u64, // increases for every segment this session, unlike the wrapping file number
+  pub fileName   : String,
+  pub startTime  : DateTime<Utc>,
+  pub durationSec: f64,
+  pub sizeB      : u64,
+  pub state      : SegmentState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentRingStatus {
+  pub completedSegments    : usize,
+  pub bufferedSec          : f64,
+  pub inProgress           : Option<String>,
+  pub newestCompletedAgeSec: Option<f64>, // grows without bound if ffmpeg stops producing segments
+}
+
+/// In memory index of one camera's LiveRecording segments built from ffmpeg's `-segment_list`
+/// csv, so nothing has to trust file modification times or pick up the segment ffmpeg is still writing
+pub struct SegmentRing {
+  state   : Mutex<RingState>,
+  _watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+#[derive(Default)]
+struct RingState {
+  directory  : PathBuf,
+  listPath   : PathBuf,
+  wrap       : usize,
+  readOffset : u64,
+  anchor     : Option<DateTime<Utc>>, // wall clock time of pts 0 for the current ffmpeg run
+  nextIndex  : u64,
+  completed  : VecDeque<Segment>,
+  pendingLine: String,
+}
+
+impl SegmentRing {
+  /// The ring for a camera, created empty the first time it is asked for
+  pub fn forCamera(camera: &str) -> Arc<SegmentRing> {
+    rings().lock().unwrap()
+      .entry(camera.to_string())
+      .or_insert_with(|| Arc::new(SegmentRing {
+        state   : Mutex::new(RingState::default()),
+        _watcher: Mutex::new(None),
+      }))
+      .clone()
+  }
+
+  /// Status of every camera's ring by camera name
+  pub fn statuses() -> BTreeMap<String, SegmentRingStatus> {
+    let rings: Vec<(String, Arc<SegmentRing>)> = rings().lock().unwrap()
+      .iter()
+      .map(|(camera, ring)| (camera.clone(), ring.clone()))
+      .collect();
+    rings.into_iter().map(|(camera, ring)| (camera, ring.status())).collect()
+  }
+
+  /// Starts following the segment list that ffmpeg writes into directory, wrap is the number of
+  /// files ffmpeg cycles through before overwriting
+  pub fn follow(&self, camera: &str, directory: &Path, wrap: usize) -> Result<(), Box<dyn Error>> {
+    self.state.lock().unwrap().restart(directory, wrap);
+
+    let listPath = directory.join(SEGMENT_LIST_FILE_NAME);
+    let camera = camera.to_string();
+    let mut watcher = notify::recommended_watcher(move |res: NotifyResult<Event>| {
+      match res {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+          && event.paths.iter().any(|p| p.ends_with(SEGMENT_LIST_FILE_NAME)) => {
+          SegmentRing::forCamera(&camera).refresh();
+        }
+        Ok(_) => (),
+        Err(e) => error!("Segment list watch error: {}", e),
+      }
+    })?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    *self._watcher.lock().unwrap() = Some(watcher);
+    info!("Segment ring following {}", listPath.display());
+    Ok(())
+  }
+
+  /// Reads any lines ffmpeg appended to the segment list since the last refresh
+  pub fn refresh(&self) {
+    let mut state = self.state.lock().unwrap();
+    if let Err(e) = state.readNewLines() {
+      warn!("Failed to read segment list: {}", e);
+    }
+  }
+
+  /// Completed segments overlapping the time range, oldest first
+  pub fn completedBetween(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Segment> {
+    self.refresh();
+    let state = self.state.lock().unwrap();
+    state.completed.iter()
+      .filter(|s| s.startTime < to && s.endTime() > from)
+      .cloned()
+      .collect()
+  }
+
+  /// Every segment currently on disk including the one being written, oldest first
+  pub fn segments(&self) -> Vec<Segment> {
+    self.refresh();
+    let state = self.state.lock().unwrap();
+    let mut segments: Vec<Segment> = state.completed.iter().cloned().collect();
+    segments.extend(state.inProgress());
+    segments
+  }
+
+  /// Polls until a completed segment reaches past time, returns false if it never did
+  pub async fn waitForCompletedUntil(&self, time: DateTime<Utc>, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+      self.refresh();
+      let reached = self.state.lock().unwrap().completed.back().map(|s| s.endTime() >= time).unwrap_or(false);
+      if reached { return true; }
+      if tokio::time::Instant::now() >= deadline { return false; }
+      tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+  }
+
+  pub fn path(&self, segment: &Segment) -> PathBuf {
+    self.state.lock().unwrap().directory.join(&segment.fileName)
+  }
+
+  pub fn status(&self) -> SegmentRingStatus {
+    self.refresh();
+    let state = self.state.lock().unwrap();
+    SegmentRingStatus {
+      completedSegments    : state.completed.len(),
+      bufferedSec          : state.completed.iter().map(|s| s.durationSec).sum(),
+      inProgress           : state.inProgress().map(|s| s.fileName),
+      newestCompletedAgeSec: state.completed.back().map(|s| secondsBetween(s.endTime(), Utc::now())),
+    }
+  }
+}
+
+impl Segment {
+  pub fn endTime(&self) -> DateTime<Utc> {
+    self.startTime + TimeDelta::milliseconds((self.durationSec * 1000.0) as i64)
+  }
+}
+
+impl RingState {
+  fn readNewLines(&mut self) -> Result<(), Box<dyn Error>> {
+    if self.listPath.as_os_str().is_empty() { return Ok(()); }
+    let mut file = match fs::File::open(&self.listPath) {
+      Ok(file) => file,
+      Err(_)   => return Ok(()), //ffmpeg has not closed its first segment yet
+    };
+
+    let length = file.metadata()?.len();
+    if length < self.readOffset {
+      info!("Segment list was truncated, ffmpeg restarted");
+      self.completed.retain(|s| !s.fileName.starts_with("output000.")); //a new run starts by overwriting the first file
+      self.readOffset  = 0;
+      self.anchor      = None;
+      self.pendingLine = String::new();
+    }
+    if length == self.readOffset { return Ok(()); }
+
+    file.seek(SeekFrom::Start(self.readOffset))?;
+    let mut appended = String::new();
+    file.read_to_string(&mut appended)?;
+    self.readOffset += appended.len() as u64;
+
+    let text = std::mem::take(&mut self.pendingLine) + &appended;
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    self.pendingLine = lines.pop().unwrap_or_default().to_string(); //ffmpeg may be mid line
+
+    //the wall clock is only read once per ffmpeg run so later clock jumps cannot reorder segments,
+    //the newest line just ended so it pins pts to the wall clock
+    if self.anchor.is_none() {
+      if let Some(endSec) = lines.iter().rev().find_map(|l| l.trim().rsplit(',').next()?.parse::<f64>().ok()) {
+        self.anchor = Some(Utc::now() - TimeDelta::milliseconds((endSec * 1000.0) as i64));
+      }
+    }
+    for line in lines {
+      if let Err(e) = self.addLine(line.trim()) {
+        warn!("Skipping segment list line {:?}: {}", line, e);
+      }
+    }
+    Ok(())
+  }
+
+  /// Starts over on a new directory, indices carry on so readers that remember the last index
+  /// they saw, like the archive recorder, do not skip the new segments
+  fn restart(&mut self, directory: &Path, wrap: usize) {
+    *self = RingState {
+      directory: directory.to_path_buf(),
+      listPath : directory.join(SEGMENT_LIST_FILE_NAME),
+      wrap     : wrap,
+      nextIndex: self.nextIndex,
+      ..Default::default()
+    };
+  }
+
+  /// Lines look like `output003.ts,15.000000,20.000000`, times are seconds since ffmpeg started
+  fn addLine(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+    if line.is_empty() { return Ok(()); }
+    let fields: Vec<&str> = line.rsplitn(3, ',').collect();
+    if fields.len() != 3 { return Err("expected name,start,end".into()); }
+    let endSec  : f64 = fields[0].parse()?;
+    let startSec: f64 = fields[1].parse()?;
+    let fileName = fields[2].trim_matches('"').to_string();
+
+    let anchor = self.anchor.ok_or("no wall clock anchor for this ffmpeg run")?;
+    let sizeB = fs::metadata(self.directory.join(&fileName)).map(|m| m.len()).unwrap_or(0);
+
+    self.completed.retain(|s| s.fileName != fileName); //ffmpeg wrapped around and overwrote it
+    self.completed.push_back(Segment {
+      index      : self.nextIndex,
+      fileName   : fileName,
+      startTime  : anchor + TimeDelta::milliseconds((startSec * 1000.0) as i64),
+      durationSec: endSec - startSec,
+      sizeB      : sizeB,
+      state      : SegmentState::Completed,
+    });
+    self.nextIndex += 1;
+    while self.wrap > 0 && self.completed.len() > self.wrap {
+      self.completed.pop_front();
+    }
+    let next = self.inProgress().map(|s| s.fileName);
+    self.completed.retain(|s| Some(&s.fileName) != next.as_ref()); //being overwritten right now
+    debug!("Segment ring: {} completed segments", self.completed.len());
+    Ok(())
+  }
+
+  /// ffmpeg moves straight on to the next numbered file once a segment is listed
+  fn inProgress(&self) -> Option<Segment> {
+    let last = self.completed.back()?;
+    let number: usize = last.fileName.trim_start_matches("output").split('.').next()?.parse().ok()?;
+    let extension = last.fileName.rsplit('.').next()?;
+    let startTime = last.endTime();
+    Some(Segment {
+      index      : last.index + 1,
+      fileName   : format!("output{:03}.{}", (number + 1) % self.wrap.max(1), extension),
+      startTime  : startTime,
+      durationSec: secondsBetween(startTime, Utc::now()).max(0.0),
+      sizeB      : 0,
+      state      : SegmentState::InProgress,
+    })
+  }
+}
+
+fn rings() -> &'static Mutex<BTreeMap<String, Arc<SegmentRing>>> {
+  SEGMENT_RINGS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn secondsBetween(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+  (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ring(wrap: usize) -> (RingState, DateTime<Utc>) {
+    let anchor = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    (RingState { wrap: wrap, anchor: Some(anchor), ..Default::default() }, anchor)
+  }
+
+  fn fileNames(state: &RingState) -> Vec<&str> {
+    state.completed.iter().map(|s| s.fileName.as_str()).collect()
+  }
+
+  #[test]
+  fn addsLinesAsCompletedSegments() {
+    let (mut state, anchor) = ring(5);
+    state.addLine("output000.ts,0.000000,5.000000").unwrap();
+    state.addLine("\"output001.ts\",5.000000,10.500000").unwrap();
+
+    assert_eq!(fileNames(&state), ["output000.ts", "output001.ts"]);
+    let second = &state.completed[1];
+    assert_eq!(second.index, 1);
+    assert_eq!(second.startTime, anchor + TimeDelta::seconds(5));
+    assert_eq!(second.endTime(), anchor + TimeDelta::milliseconds(10_500));
+    assert_eq!(state.inProgress().unwrap().fileName, "output002.ts");
+  }
+
+  #[test]
+  fn dropsTheSegmentBeingOverwrittenWhenWrapping() {
+    let (mut state, anchor) = ring(3);
+    for line in ["output000.ts,0,5", "output001.ts,5,10", "output002.ts,10,15"] {
+      state.addLine(line).unwrap();
+    }
+    assert_eq!(fileNames(&state), ["output001.ts", "output002.ts"]); //output000 is being written again
+    assert_eq!(state.inProgress().unwrap().fileName, "output000.ts");
+
+    state.addLine("output000.ts,15,20").unwrap();
+    assert_eq!(fileNames(&state), ["output002.ts", "output000.ts"]);
+    let newest = state.completed.back().unwrap();
+    assert_eq!(newest.index, 3);
+    assert_eq!(newest.startTime, anchor + TimeDelta::seconds(15));
+  }
+
+  #[test]
+  fn rejectsBadLines() {
+    let (mut state, _) = ring(3);
+    assert!(state.addLine("").is_ok());
+    assert!(state.addLine("output000.ts,5").is_err());
+    assert!(state.addLine("output000.ts,zero,5").is_err());
+    assert!(state.completed.is_empty());
+
+    let mut unanchored = RingState { wrap: 3, ..Default::default() };
+    assert!(unanchored.addLine("output000.ts,0,5").is_err());
+  }
+  #[test]
+  fn keepsCountingIndicesInANewDirectory() {
+    let (mut state, anchor) = ring(5);
+    state.addLine("output000.ts,0,5").unwrap();
+    state.addLine("output001.ts,5,10").unwrap();
+
+    state.restart(Path::new("/x/LiveRecording/front/"), 5); //the storage root switched
+    state.anchor = Some(anchor);
+    state.addLine("output000.ts,0,5").unwrap();
+    assert_eq!(fileNames(&state), ["output000.ts"]);
+    assert_eq!(state.completed[0].index, 2);
+  }
+}
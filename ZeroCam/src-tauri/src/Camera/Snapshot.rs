@@ -0,0 +1,96 @@
+use crate::Camera::FfmpegPipeline::{FfmpegInput, FfmpegOutput, FfmpegPipeline};
+use crate::Camera::MotionFeed::MotionFeed;
+use crate::Config;
+use crate::Storage::StorageRoot::{partPath, StorageRoot};
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+const FRAME_WAIT      : Duration = Duration::from_secs(2); // several motion feed frames
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10); // encoding a single frame
+
+/// A still saved from a camera's live feed
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+  pub camera  : String,
+  pub path    : String,
+  pub fileName: String,
+  pub takenAt : DateTime<Utc>,
+  pub sizeB   : u64,
+}
+
+/// Snapshots every camera, or only the camera named, one camera failing doesn't lose the others
+pub async fn takeSnapshots(camera: Option<&str>) -> Result<Vec<Snapshot>, Box<dyn Error>> {
+  let config = Config::getConfig().await?;
+  let cameras: Vec<String> = match camera {
+    Some(name) => vec![config.camera(name).ok_or(format!("No camera is named {}", name))?.name.clone()],
+    None       => config.camera_input.iter().map(|c| c.name.clone()).collect(),
+  };
+
+  let mut snapshots: Vec<Snapshot> = Vec::new();
+  let mut lastError: Option<String> = None;
+  for camera in cameras {
+    match takeSnapshot(&camera).await.map_err(|e| e.to_string()) {
+      Ok(snapshot) => snapshots.push(snapshot),
+      Err(e)       => {
+        error!("Snapshot of camera {} failed: {}", camera, e);
+        lastError = Some(format!("{}: {}", camera, e));
+      }
+    }
+  }
+  match (snapshots.is_empty(), lastError) {
+    (true, Some(e)) => Err(e.into()),
+    _               => Ok(snapshots),
+  }
+}
+
+/// Saves the newest frame of the camera's live motion feed as a jpeg named after the camera and
+/// the time it was taken, gray at the motion listener's resolution and orientation
+pub async fn takeSnapshot(camera: &str) -> Result<Snapshot, Box<dyn Error>> {
+  let config = Config::getConfig().await?;
+  let feed = MotionFeed::forCamera(camera, &config.motion_listener.feedResolution())?;
+  let frame = feed.waitForFrame(feed.sequence(), FRAME_WAIT).await //a fresh frame, so a stopped camera isn't shown as live
+    .ok_or(format!("Camera {} sent no frame within {}sec, is it running?", camera, FRAME_WAIT.as_secs()))?;
+  let takenAt = Utc::now();
+  let fileName = format!("{}_{}.jpg", camera, takenAt);
+  let path = StorageRoot::global().snapshotsPath().join(&fileName);
+  let framePath = partPath(&path.with_extension("gray"));
+  fs::write(&framePath, &frame.pixels)?;
+
+  let pipeline = buildSnapshotPipeline(&framePath.display().to_string(), feed.width(), feed.height(), &path.display().to_string());
+  let snapshot = tokio::time::timeout(SNAPSHOT_TIMEOUT, pipeline.run()).await;
+  let _ = fs::remove_file(&framePath);
+  let snapshot = snapshot.map_err(|_| format!("Snapshot of camera {} was not encoded within {}sec", camera, SNAPSHOT_TIMEOUT.as_secs()))?;
+  if let Err(e) = snapshot {
+    let _ = fs::remove_file(&path);
+    return Err(format!("Snapshot failed: {}", e).into());
+  }
+
+  let path = path.display().to_string();
+  let sizeB = fs::metadata(&path).map_err(|e| format!("Snapshot {} was not written: {}", path, e))?.len();
+  info!("Snapshot saved: {}", fileName);
+  Ok(Snapshot {
+    camera  : camera.to_string(),
+    path    : path,
+    fileName: fileName,
+    takenAt : takenAt,
+    sizeB   : sizeB,
+  })
+}
+
+/// Encodes one raw gray motion feed frame as a jpeg
+pub fn buildSnapshotPipeline(framePath: &str, width: usize, height: usize, snapshotPath: &str) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .globalFlag("-y")
+    .input(FfmpegInput::new(framePath)
+      .format("rawvideo")
+      .option("-pix_fmt", "gray")
+      .option("-video_size", format!("{}x{}", width, height)))
+    .output(FfmpegOutput::new("snapshot", snapshotPath)
+      .option("-frames:v", "1")
+      .jpegQuality(2))
+}
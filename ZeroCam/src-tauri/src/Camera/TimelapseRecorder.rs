@@ -0,0 +1,130 @@
+use crate::Camera::CameraController::CameraController;
+use crate::Camera::FfmpegPipeline::{FfmpegInput, FfmpegOutput, FfmpegPipeline};
+use crate::Camera::MotionListener::getParkedState;
+use crate::Camera::SegmentRing::{SegmentRing, SegmentState};
+use crate::Config;
+use crate::Config::ConfigFile;
+use crate::Storage::StorageRoot::StorageRoot;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use log::{error, info};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// While parked, keeps one full resolution frame every interval_sec from the newest recorded
+/// segment, in a hidden directory per day, and assembles each finished day into a timelapse clip.
+/// A frame is the first of its segment and is timed by the segment's start, so frames can't be
+/// closer together than the segment size
+pub struct TimelapseRecorder {
+  cameraController: CameraController,
+  config          : ConfigFile,
+}
+
+impl TimelapseRecorder {
+  pub async fn new() -> Result<TimelapseRecorder, Box<dyn Error>> {
+    Ok(Self {
+      cameraController: CameraController::new().await?,
+      config          : Config::getConfig().await?,
+    })
+  }
+
+  /// One capture loop per camera, days left over from a previous run are assembled first
+  pub fn start(self: Arc<Self>) {
+    if !self.config.timelapse.enabled {
+      return;
+    }
+    for camera in self.config.camera_input.iter().map(|c| c.name.clone()) {
+      let recorder = self.clone();
+      tokio::spawn(async move {
+        recorder.run(camera).await;
+      });
+    }
+  }
+
+  async fn run(&self, camera: String) {
+    let interval = Duration::from_secs(self.config.timelapse.interval_sec.max(1));
+    let mut day: Option<NaiveDate> = None;
+    loop {
+      let today = Local::now().date_naive();
+      if day != Some(today) {
+        self.assembleFinishedDays(&camera, today).await;
+        day = Some(today);
+      }
+      if getParkedState() {
+        if let Err(e) = self.capture(&camera, today).await.map_err(|e| e.to_string()) {
+          error!("Failed to capture timelapse frame of camera {}: {}", camera, e);
+        }
+      }
+      tokio::time::sleep(interval).await;
+    }
+  }
+
+  /// Grabs the first frame of the newest completed segment, skipped when that segment was already used
+  async fn capture(&self, camera: &str, today: NaiveDate) -> Result<(), Box<dyn Error>> {
+    let ring = SegmentRing::forCamera(camera);
+    let Some(segment) = ring.segments().into_iter().rev().find(|s| s.state == SegmentState::Completed) else {
+      return Ok(()); //nothing recorded yet
+    };
+    let dayPath = framesPath(camera).join(today.to_string());
+    let framePath = dayPath.join(format!("{}.jpg", segment.startTime.timestamp_millis())); //same width so the glob sorts by time
+    if framePath.exists() {
+      return Ok(());
+    }
+    fs::create_dir_all(&dayPath)?;
+
+    buildFramePipeline(&ring.path(&segment).display().to_string(), &framePath.display().to_string()).run().await?;
+    Ok(())
+  }
+
+  /// Turns every day before today into a timelapse clip, a day that fails is kept for the next attempt
+  async fn assembleFinishedDays(&self, camera: &str, today: NaiveDate) {
+    let days: Vec<(NaiveDate, PathBuf)> = fs::read_dir(framesPath(camera))
+      .map(|entries| entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| Some((e.file_name().to_str()?.parse::<NaiveDate>().ok()?, e.path())))
+        .filter(|(day, _)| *day < today)
+        .collect())
+      .unwrap_or_default();
+
+    for (day, dayPath) in days {
+      let Some((from, to)) = frameTimes(&dayPath) else {
+        let _ = fs::remove_dir_all(&dayPath); //no frames that day
+        continue;
+      };
+      let assembled = self.cameraController.timelapse(camera, &dayPath, from, to, self.config.timelapse.fps).await.map_err(|e| e.to_string());
+      match assembled {
+        Ok(result) => {
+          info!("Timelapse of camera {} for {} saved: {}", camera, day, result.fileName);
+          let _ = fs::remove_dir_all(&dayPath);
+        }
+        Err(e) => error!("Timelapse of camera {} for {} failed: {}", camera, day, e),
+      }
+    }
+  }
+}
+
+/// Not a clip so the catalog and backup skip it, the retention policy only counts its size
+fn framesPath(camera: &str) -> PathBuf {
+  StorageRoot::global().clipsPath().join(format!(".timelapse-{}", camera))
+}
+
+/// Capture times of the first and last frame of a day
+fn frameTimes(dayPath: &Path) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+  let times: Vec<DateTime<Utc>> = fs::read_dir(dayPath).ok()?
+    .filter_map(|e| e.ok())
+    .filter_map(|e| DateTime::from_timestamp_millis(e.path().file_stem()?.to_str()?.parse().ok()?))
+    .collect();
+  Some((*times.iter().min()?, *times.iter().max()?))
+}
+
+pub fn buildFramePipeline(segmentPath: &str, framePath: &str) -> FfmpegPipeline {
+  FfmpegPipeline::new()
+    .globalFlag("-y")
+    .input(FfmpegInput::new(segmentPath))
+    .output(FfmpegOutput::new("frame", framePath)
+      .option("-frames:v", "1")
+      .jpegQuality(2))
+}
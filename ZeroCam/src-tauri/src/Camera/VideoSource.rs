@@ -0,0 +1,34 @@
+use crate::Camera::FfmpegPipeline::FfmpegInput;
+use crate::Config::VideoSource;
+
+impl VideoSource {
+  /// the pipeline input for this source, everything after the input is the same regardless of
+  /// where the video comes from
+  pub fn input(&self, resolution: &str, fps: &str) -> FfmpegInput {
+    match self {
+      VideoSource::V4l2 { device, input_format } => FfmpegInput::new(device)
+        .format("v4l2"                       ) // demuxer format v4l2
+        .option("-input_format", input_format)
+        .option("-framerate"   , fps         )
+        .option("-video_size"  , resolution  ),
+      VideoSource::Stream { url } if url.starts_with("rtsp://") => FfmpegInput::new(url)
+        .option("-rtsp_transport", "tcp"), // udp drops frames over wifi
+      VideoSource::Stream { url } => FfmpegInput::new(url),
+      VideoSource::File { path } => FfmpegInput::new(path)
+        .flag("-re"                 ) // read at native frame rate as if it were live
+        .option("-stream_loop", "-1"), // loop forever
+      VideoSource::TestSrc => FfmpegInput::new(format!("testsrc=size={}:rate={}", resolution, fps))
+        .flag("-re")
+        .format("lavfi"),
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    match self {
+      VideoSource::V4l2 { device, .. } => format!("v4l2 device {}", device),
+      VideoSource::Stream { url }      => format!("stream {}", url),
+      VideoSource::File { path }       => format!("looping file {}", path),
+      VideoSource::TestSrc             => "ffmpeg testsrc".to_string(),
+    }
+  }
+}
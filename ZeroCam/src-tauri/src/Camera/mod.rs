@@ -1,3 +1,17 @@
+pub mod ArchiveRecorder;
+pub mod AudioSource;
 pub mod CameraController;
+pub mod ClipPreview;
+pub mod ContainerFormat;
+pub mod FfProbe;
+pub mod FfmpegPipeline;
 pub mod ClipScheduler;
+pub mod MotionDetector;
+pub mod MotionFeed;
 pub mod MotionListener;
+pub mod OutputSettings;
+pub mod Overlay;
+pub mod SegmentRing;
+pub mod Snapshot;
+pub mod TimelapseRecorder;
+pub mod VideoSource;
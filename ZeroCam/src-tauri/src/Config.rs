@@ -1,61 +1,188 @@
+use crate::Camera::ClipScheduler::TriggerSource;
+
 use log::info;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 
 #[derive(Debug, Deserialize)]
 pub struct ConfigFile {
   pub telegram_key          : String,
-  pub camera_input          : CameraInput,
+  pub camera_input          : Vec<CameraInput>,
+  pub clip                  : Clip, // shared by every camera so a trigger clips them all the same way
+  pub archive               : Archive,
+  pub timelapse             : Timelapse,
   pub motion_listener       : MotionListener,
   pub gui_stream_output     : GUIStreamOutput,
   pub internet_stream_output: InternetStreamOutput,
   pub g_cloud               : GCloud,
+  pub retention             : Retention,
+  pub storage               : Storage,
+  pub encryption            : Encryption,
+  pub ledger                : Ledger,
+  pub overlay               : Overlay,
   pub hotspot_networks      : Vec<String>
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CameraInput {
-  pub resolution: String,
-  pub fps       : String,
-  pub encoder   : String,
-  pub clip      : Clip
+  pub name       : String,      // eg. front, prefixes its clips and names its streams
+  pub source     : VideoSource,
+  pub resolution : String,
+  pub fps        : String,
+  pub encoding   : Encoding,    // of the stored recording
+  pub orientation: Orientation,
+  #[serde(default)]
+  pub audio      : Option<Audio>, // video only when missing
+}
+
+/// How an output is encoded
+#[derive(Debug, Deserialize)]
+pub struct Encoding {
+  pub codec    : String, // eg. libx264, h264_v4l2m2m on a raspberry pi
+  pub preset   : String, // empty for encoders without presets
+  pub bit_rate : String,
+  pub pix_fmt  : String,
+  pub grayscale: bool,
+}
+
+/// Corrects for how the camera is mounted
+#[derive(Debug, Deserialize)]
+pub struct Orientation {
+  pub rotate: u16, // clockwise degrees, 0 90 180 or 270, 90 and 270 swap the output's width and height
+  pub hflip : bool,
+  pub vflip : bool,
+}
+
+/// Where the main ffmpeg pipeline reads its video from
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VideoSource {
+  V4l2   { device: String, input_format: String }, // usb webcam eg. /dev/video0 mjpeg
+  Stream { url: String },                          // ip camera rtsp:// or http:// url
+  File   { path: String },                         // video file played on a loop
+  TestSrc,                                         // ffmpeg synthetic test pattern
+}
+
+/// Microphone recorded into the segments and clips, the streams stay video only
+#[derive(Debug, Deserialize)]
+pub struct Audio {
+  pub source     : AudioSource,
+  pub codec      : String, // eg. aac
+  pub bit_rate   : String,
+  pub sample_rate: String,
+  pub muted      : bool,   // at startup, can be toggled from the GUI and telegram
+}
+
+/// Where the main ffmpeg pipeline reads its audio from
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioSource {
+  Alsa  { device: String }, // eg. hw:1,0 or default
+  Pulse { device: String }, // pulseaudio source name or default
+  Sine  { frequency: u32 }, // ffmpeg synthetic tone
+  Anullsrc,                 // ffmpeg synthetic silence
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Clip {
-  pub segment_size_sec     : String,
-  pub segments             : String,
-  pub timer_before_clip_sec: u64,
-  pub cooldown_sec         : i64,
-  pub disk_full_buffer_gb  : i64
+  pub segment_size_sec   : String,
+  pub segments           : String,
+  pub pre_event_sec      : u64, // how much of the clip comes from before the trigger
+  pub post_event_sec     : u64, // how long to keep recording after the trigger
+  pub cooldown_sec       : i64,
+  pub disk_full_buffer_gb: i64,
+  #[serde(default)]
+  pub segment_format     : SegmentFormat,
+  #[serde(default)]
+  pub clip_format        : ClipFormat,
+}
+
+/// Container of the LiveRecording segments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentFormat {
+  #[default]
+  Mpegts,
+  Fmp4, // fragmented mp4, readable up to the last fragment after a power cut
+  Mkv,
+}
+
+/// Container of the finished clips
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipFormat {
+  #[default]
+  Mp4,  // faststart so browsers and drive previews play before the whole file has loaded
+  Fmp4,
+  Mkv,
+}
+
+/// Continuous recording kept as one clip per period besides the triggered clips
+#[derive(Debug, Deserialize)]
+pub struct Archive {
+  pub mode      : ArchiveMode, // the mode at start up, it can be changed at runtime
+  pub period_min: u64,         // length of each archive file, periods start on the hour
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveMode {
+  #[default]
+  Off,
+  Always,
+  Driving, // only while not parked
+}
+
+/// Frames captured while parked and assembled into one clip per day
+#[derive(Debug, Deserialize)]
+pub struct Timelapse {
+  pub enabled     : bool,
+  pub interval_sec: u64, // between captured frames, at most one frame per segment so below clip.segment_size_sec it is one a segment
+  pub fps         : u32, // of the assembled clip, eg. 24 turns a 30sec interval into 12 minutes a second
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MotionListener {
-  pub sensitivity_inverse : f64,
+  pub algorithm           : MotionAlgorithm,
+  pub sensitivity_inverse : f64, // pixel difference that counts as changed, frame_diff only
+  pub flow_threshold_px   : f64, // how far a pixel has to move between frames, optical_flow only
   pub threshold_sum_kilo  : f64,
   pub frame_delay_millisec: u64,
   pub trigger_duration    : i8,
-  pub resolution          : String,
-  pub bit_rate            : String,
-  pub fps                 : String
+  pub resolution          : String, // of the raw frames compared, keep it small
+  pub fps                 : String,
+  pub orientation         : Orientation,
+}
+
+/// How a camera's motion feed is turned into a score, see Camera/MotionDetector.rs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionAlgorithm {
+  #[default]
+  FrameDiff,   // each frame against the one before, cheap and fine for a quiet parking garage
+  Mog2,        // background subtraction, learns swaying trees and flickering lights of a street
+  Knn,         // background subtraction, better than mog2 when little of the frame moves
+  OpticalFlow, // how far pixels move between frames, ignores lighting changes
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GUIStreamOutput {
-  pub resolution: String,
-  pub bit_rate  : String,
-  pub fps       : String
+  pub resolution : String,
+  pub fps        : String,
+  pub encoding   : Encoding,
+  pub orientation: Orientation,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct InternetStreamOutput {
-  pub url       : String,
-  pub username  : String,
-  pub password  : String,
-  pub resolution: String,
-  pub bit_rate  : String,
-  pub fps       : String
+  pub url        : String, // public base url, each camera streams under its name
+  pub username   : String,
+  pub password   : String,
+  pub resolution : String,
+  pub fps        : String,
+  pub encoding   : Encoding,
+  pub orientation: Orientation,
 }
 
 #[derive(Debug, Deserialize)]
@@ -64,12 +191,111 @@ pub struct GCloud {
   pub backup_scheduler_timeout_sec: u64,
 }
 
+/// Rules for deleting local clips, 0 means no limit, locked clips are never deleted
+#[derive(Debug, Deserialize)]
+pub struct Retention {
+  pub check_interval_sec: u64,
+  pub max_age_days      : u64,
+  pub max_clips         : usize,
+  pub max_total_gb      : f64,
+  #[serde(default)]
+  pub triggers          : HashMap<TriggerSource, TriggerQuota>, // overrides per trigger type
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TriggerQuota {
+  pub max_age_days: u64, // replaces the global max age for this trigger type
+  pub max_clips   : usize,
+}
+
+/// age encryption of finished clips, drive only ever gets the encrypted copy
+#[derive(Debug, Deserialize)]
+pub struct Encryption {
+  pub enabled             : bool,
+  pub recipient           : String, // age public key age1..., its secret key never goes on the device
+  pub keep_local_plaintext: bool,   // false encrypts the local clips too, the GUI can't play or preview them then
+}
+
+/// Hash chained record of every clip, see Storage/ClipLedger.rs
+#[derive(Debug, Deserialize)]
+pub struct Ledger {
+  pub sign: bool, // sign each entry with the device key, made in lib/zerocam/ledgerKey on first use
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Storage {
+  pub root              : String, // eg. a usb ssd mount point, empty keeps everything in lib/zerocam
+  pub check_interval_sec: u64,
+}
+
+/// Date, time and device label burned into the chosen outputs
+#[derive(Debug, Deserialize)]
+pub struct Overlay {
+  pub device_label   : String,
+  pub timezone       : String, // IANA name eg. Europe/London, empty for the system timezone
+  pub time_format    : String, // strftime eg. %Y-%m-%d %H:%M:%S
+  pub font_size      : u32,
+  pub font_file      : String, // empty lets fontconfig pick
+  pub storage        : bool,
+  pub internet_stream: bool,
+  pub gui_stream     : bool,
+}
+
+impl ConfigFile {
+  pub fn camera(&self, name: &str) -> Option<&CameraInput> {
+    self.camera_input.iter().find(|c| c.name == name)
+  }
+}
+
 pub async fn getConfig() -> Result<ConfigFile, Box<dyn Error>> {
   let yaml_str = std::fs::read_to_string("../lib/zerocam/config.yaml")?;
   let config: ConfigFile = serde_yaml::from_str(&yaml_str)?;
+  validateCameras(&config.camera_input)?;
+  validateOrientations(&config)?;
+  validateEncryption(&config.encryption)?;
   Ok(config)
 }
 
+/// Rather than fall back to uploading plaintext
+fn validateEncryption(encryption: &Encryption) -> Result<(), Box<dyn Error>> {
+  if encryption.enabled && !encryption.recipient.trim().starts_with("age1") {
+    return Err("encryption.recipient needs an age public key (age1...) when encryption is enabled".into());
+  }
+  Ok(())
+}
+
+/// Camera names end up in file names, directories and stream urls
+fn validateCameras(cameras: &Vec<CameraInput>) -> Result<(), Box<dyn Error>> {
+  if cameras.is_empty() {
+    return Err("camera_input needs at least one camera".into());
+  }
+  for (i, camera) in cameras.iter().enumerate() {
+    if camera.name.is_empty() || !camera.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+      return Err(format!("Camera name {:?} may only use letters, numbers, _ and -", camera.name).into());
+    }
+    if cameras[..i].iter().any(|c| c.name == camera.name) {
+      return Err(format!("Camera name {} is used more than once", camera.name).into());
+    }
+  }
+  Ok(())
+}
+
+/// The output filters only know quarter turns, anything else would be silently left unrotated
+fn validateOrientations(config: &ConfigFile) -> Result<(), Box<dyn Error>> {
+  let mut orientations: Vec<(String, &Orientation)> = config.camera_input.iter()
+    .map(|c| (format!("camera_input {}", c.name), &c.orientation))
+    .collect();
+  orientations.push(("motion_listener".to_string(), &config.motion_listener.orientation));
+  orientations.push(("gui_stream_output".to_string(), &config.gui_stream_output.orientation));
+  orientations.push(("internet_stream_output".to_string(), &config.internet_stream_output.orientation));
+  for (output, orientation) in orientations {
+    if orientation.rotate % 90 != 0 {
+      return Err(format!("{} rotates by {} degrees, only 0, 90, 180 and 270 are possible", output, orientation.rotate).into());
+    }
+  }
+  Ok(())
+}
+
 pub async fn getConfigAsString() -> Result<String, Box<dyn Error>> {
   let yaml_str = std::fs::read_to_string("../lib/zerocam/config.yaml")?;
   Ok(yaml_str)
@@ -84,3 +310,148 @@ pub fn setConfigFromString(configString: String) -> Result<(), Box<dyn Error>> {
 pub async fn showConfig() {
   info!("{:?}", getConfig().await.unwrap());
 }
+
+/// A complete config for unit tests, independent of the deployed config.yaml
+#[cfg(test)]
+pub fn testConfig() -> ConfigFile {
+  serde_yaml::from_str(TEST_CONFIG).unwrap()
+}
+
+#[cfg(test)]
+const TEST_CONFIG: &str = r#"
+telegram_key: "0000000000:test"
+camera_input:
+  - name: front
+    source:
+      type: v4l2
+      device: /dev/video0
+      input_format: mjpeg
+    resolution: 1920x1080
+    fps: 15
+    encoding:
+      codec: libx264
+      preset: ""
+      bit_rate: 20M
+      pix_fmt: yuv420p
+      grayscale: true
+    orientation:
+      rotate: 0
+      hflip: false
+      vflip: false
+    audio: null
+clip:
+  segment_size_sec: 5
+  segments: 5
+  pre_event_sec: 10
+  post_event_sec: 5
+  cooldown_sec: 5
+  disk_full_buffer_gb: 2
+  segment_format: mpegts
+  clip_format: mp4
+archive:
+  mode: off
+  period_min: 60
+timelapse:
+  enabled: false
+  interval_sec: 30
+  fps: 24
+motion_listener:
+  algorithm: frame_diff
+  sensitivity_inverse: 40
+  flow_threshold_px: 1.0
+  threshold_sum_kilo: 250
+  frame_delay_millisec: 120
+  trigger_duration: 5
+  resolution: 640x360
+  fps: 10
+  orientation:
+    rotate: 0
+    hflip: false
+    vflip: false
+gui_stream_output:
+  resolution: 1920x1080
+  fps: 10
+  encoding:
+    codec: libx264
+    preset: ultrafast
+    bit_rate: 80k
+    pix_fmt: yuv420p
+    grayscale: true
+  orientation:
+    rotate: 0
+    hflip: false
+    vflip: false
+internet_stream_output:
+  url: https://stream.example.com/
+  username: test
+  password: test
+  resolution: 1920x1080
+  fps: 10
+  encoding:
+    codec: libx264
+    preset: ultrafast
+    bit_rate: 200k
+    pix_fmt: yuv420p
+    grayscale: true
+  orientation:
+    rotate: 0
+    hflip: false
+    vflip: false
+g_cloud:
+  limit_gb: 14
+  backup_scheduler_timeout_sec: 360
+retention:
+  check_interval_sec: 600
+  max_age_days: 30
+  max_clips: 0
+  max_total_gb: 0
+  triggers:
+    motion:
+      max_age_days: 7
+      max_clips: 200
+    gui:
+      max_age_days: 90
+      max_clips: 0
+    telegram:
+      max_age_days: 90
+      max_clips: 0
+    archive:
+      max_age_days: 7
+      max_clips: 0
+storage:
+  root: ""
+  check_interval_sec: 10
+encryption:
+  enabled: false
+  recipient: ""
+  keep_local_plaintext: true
+ledger:
+  sign: false
+overlay:
+  device_label: ZeroCam
+  timezone: ""
+  time_format: "%Y-%m-%d %H:%M:%S"
+  font_size: 32
+  font_file: ""
+  storage: true
+  internet_stream: true
+  gui_stream: false
+hotspot_networks: []
+"#;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejectsRotationsThatAreNotQuarterTurns() {
+    let mut config = testConfig();
+    assert!(validateOrientations(&config).is_ok());
+
+    config.gui_stream_output.orientation.rotate = 270;
+    assert!(validateOrientations(&config).is_ok());
+
+    config.camera_input[0].orientation.rotate = 45;
+    assert!(validateOrientations(&config).is_err());
+  }
+}
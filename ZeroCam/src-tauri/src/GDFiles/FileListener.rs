@@ -1,28 +1,34 @@
+use crate::Config;
 use crate::GDFiles::BackupScheduler::BackupScheduler;
 use crate::GDFiles::GDController::GDController;
+use crate::Storage::ClipEncryption;
+use crate::Storage::StorageRoot::StorageRoot;
 
 use log::{error, info};
+use notify::event::{ModifyKind, RenameMode};
 use notify::{
   Event, EventKind, RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher,
 };
-use std::env;
+use std::error::Error;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::Duration;
 use tokio::runtime::Handle;
+use tokio::sync::broadcast::error::RecvError;
 
 pub struct FileListener {
-  _watcher: RecommendedWatcher,
+  _watcher: Arc<Mutex<RecommendedWatcher>>,
 }
 
 impl FileListener {
-  pub async fn new(backupScheduler: Arc<BackupScheduler>) -> NotifyResult<Self> {
+  pub async fn new(backupScheduler: Arc<BackupScheduler>) -> Result<Self, Box<dyn Error>> {
     let runtimeHandle = Handle::current();
+    let clipExtension = Config::getConfig().await?.clip.clip_format.extension();
 
     let mut watcher = notify::recommended_watcher(move |res: NotifyResult<Event>| {
       match res {
-        Ok(event) if matches!(event.kind, EventKind::Create(_)) => {
+        Ok(event) if isFinishedClip(&event, clipExtension) => {
           let scheduler = backupScheduler.clone();
           runtimeHandle.spawn(async move {
             sleep(Duration::from_secs(5));
@@ -36,8 +42,41 @@ impl FileListener {
       }
     })?;
 
-    watcher.watch(Path::new(env::current_dir()?.parent().unwrap().join("lib/zerocam/Clips/").as_path()), RecursiveMode::Recursive)?;
+    let clipsPath = StorageRoot::global().clipsPath();
+    watcher.watch(clipsPath.as_path(), RecursiveMode::Recursive)?;
     info!("File creation listener created");
+
+    let watcher = Arc::new(Mutex::new(watcher));
+    let rewatcher = watcher.clone();
+    let mut storageEvents = StorageRoot::global().subscribe();
+    tokio::spawn(async move {
+      let mut watchedPath = clipsPath;
+      while !matches!(storageEvents.recv().await, Err(RecvError::Closed)) {
+        let newPath = StorageRoot::global().clipsPath();
+        let mut watcher = rewatcher.lock().unwrap();
+        let _ = watcher.unwatch(&watchedPath); //the old drive may already be gone
+        match watcher.watch(&newPath, RecursiveMode::Recursive) {
+          Ok(()) => info!("File creation listener moved to {}", newPath.display()),
+          Err(e) => error!("Failed to watch {}: {}", newPath.display(), e),
+        }
+        watchedPath = newPath;
+      }
+    });
+
     Ok(Self { _watcher: watcher })
   }
 }
+
+/// Clips are written under a hidden .part name and renamed once complete, so a clip arrives as
+/// either a create or a rename, previews and other files in the folder are ignored. Encrypted clips end in .age
+fn isFinishedClip(event: &Event, clipExtension: &str) -> bool {
+  let arrived = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both)));
+  let isClip = |path: &Path| {
+    let fileName = path.file_name().unwrap_or_default().to_string_lossy();
+    !fileName.starts_with('.') && Path::new(ClipEncryption::plainFileName(&fileName)).extension().map(|e| e == clipExtension).unwrap_or(false)
+  };
+  arrived && match event.kind {
+    EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => event.paths.last().map(|p| isClip(p)).unwrap_or(false), //paths are [from, to]
+    _ => event.paths.iter().any(|p| isClip(p)),
+  }
+}
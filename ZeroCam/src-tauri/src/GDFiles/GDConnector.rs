@@ -1,12 +1,13 @@
 extern crate google_drive3 as drive3;
 
+use crate::Camera::ContainerFormat;
 use drive3::{hyper_rustls, hyper_util, yup_oauth2, DriveHub};
 use drive3::{Error, Result};
-use google_drive3::api::{About, FileList};
+use google_drive3::api::{About, FileContentHints, FileContentHintsThumbnail, FileList};
 use google_drive3::common::Response;
 use google_drive3::hyper_rustls::HttpsConnector;
 use log::{info};
-use mime_guess::{from_path, Mime};
+use mime_guess::Mime;
 use std::env;
 use std::io::{Cursor};
 
@@ -100,13 +101,21 @@ impl GDClient {
       .await
   }
 
-  pub async fn uploadFile(&self, filePath: String, fileName: String, parentID: String) -> Result<Response> {
-    let mimeType: Mime = from_path(filePath.clone()).first_or_octet_stream();
+  /// thumbnail is a jpeg drive shows instead of generating its own from the video
+  pub async fn uploadFile(&self, filePath: String, fileName: String, parentID: String, thumbnail: Option<Vec<u8>>) -> Result<(Response, drive3::api::File)> {
+    let mimeType: Mime = ContainerFormat::mimeType(&filePath);
 
     let file = drive3::api::File {
       name: Some(fileName),
       mime_type: Some(mimeType.to_string()),
       parents: Some(vec![parentID]),
+      content_hints: thumbnail.map(|image| FileContentHints {
+        thumbnail: Some(FileContentHintsThumbnail {
+          image: Some(image),
+          mime_type: Some("image/jpeg".to_string()),
+        }),
+        ..Default::default()
+      }),
       ..Default::default()
     };
 
@@ -118,6 +127,6 @@ impl GDClient {
       .add_scope("https://www.googleapis.com/auth/drive")
       .upload(Cursor::new(file_content), mimeType)
       .await?;
-    Ok(res.0)
+    Ok(res)
   }
 }
@@ -1,10 +1,17 @@
+use crate::Camera::ClipPreview;
 use crate::Config;
 use crate::Config::ConfigFile;
 use crate::GDFiles::GDConnector;
+use crate::Storage::ClipCatalog::{ClipCatalog, UploadState};
+use crate::Storage::ClipEncryption;
+use crate::Storage::ClipLedger::{LEDGER_FILE_NAME, LEDGER_PATH};
+use crate::Storage::StorageRoot::{partPath, StorageRoot};
 
-use log::{debug, info};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
 use std::cmp::{min, Reverse};
-use std::{env, error::Error, fs};
+use std::path::PathBuf;
+use std::{error::Error, fs};
 
 pub struct GDController {
   gdClient  : GDConnector::GDClient,
@@ -16,7 +23,7 @@ impl GDController {
   pub async fn new() -> Result<GDController, Box<dyn Error>> {
     Ok(Self {
       gdClient  : GDConnector::GDClient::new().await?,
-      clipsPath : env::current_dir()?.parent().unwrap().join("lib/zerocam/Clips/").display().to_string(),
+      clipsPath : StorageRoot::global().clipsPath().display().to_string(),
       configFile: Config::getConfig().await?,
     })
   }
@@ -24,6 +31,7 @@ impl GDController {
   pub async fn backupNow(&self) -> Result<(), Box<dyn Error>> {
     self.checkClipFolderExistsAndFix().await?;
     self.uploadClips().await?;
+    self.uploadLedger().await?;
     Ok(())
   }
 
@@ -37,35 +45,62 @@ impl GDController {
     Ok(())
   }
 
+  /// Uploads local clips newest first, evicting the oldest unlocked clips on drive to make space.
+  /// Clips are ordered by trigger time, names start with the camera so they don't sort by age
   pub async fn uploadClips(&self) -> Result<(), Box<dyn Error>> {
     info!("Uploading local clips to GD");
-    let mut gdFileListDescending            : Vec<google_drive3::api::File> = self.gdClient.getFileListDescending(false).await.unwrap();
-    gdFileListDescending.extend(self.gdClient.getFileListDescending(true).await.unwrap());
-    gdFileListDescending.sort_by(|a, b| b.name.clone().unwrap_or_default().cmp(&a.name.clone().unwrap_or_default()));
-    let clipsFolderID                       : String                        = gdFileListDescending.iter().find(|f| f.name.clone().unwrap() == "ZeroCam Clips").unwrap().id.clone().unwrap();
-    let mut gdClipsFileListDescending       : Vec<google_drive3::api::File> = gdFileListDescending.clone().iter().filter(|f| f.parents.clone().unwrap().contains(&clipsFolderID)).cloned().collect();
-    let stringGDClipsFileListDescending     : Vec<String>                   = gdClipsFileListDescending.clone().iter().map(|f| f.name.clone().unwrap()).collect();
-    let localFileListDescending             : Vec<String>                   = self.getLocalFilesDescending()?;
-    let localFileListNotInGDDescending  : Vec<String>                   = localFileListDescending.clone().iter().filter(|f| !stringGDClipsFileListDescending.contains(f)).cloned().collect();
-
-    gdFileListDescending = gdFileListDescending.clone().iter().filter(|f| f.name.clone().unwrap() != "ZeroCam Clips").cloned().collect();
-
-    debug!("Local file list not in GD{:?}", &localFileListNotInGDDescending);
-
-    for localFile in &localFileListNotInGDDescending{
-      if &localFile > &&gdClipsFileListDescending.last().map(|f| f.name.clone().unwrap_or_default()).unwrap_or_default() {
-        let localFileSize: i64 = fs::metadata(self.clipsPath.clone() + &localFile).unwrap().len() as i64;
-        while localFileSize > self.calculateSpaceAvailable(&clipsFolderID).await? {
-          let oldestGDFile = gdClipsFileListDescending.pop().expect("No files left to delete to make space for file in GD!");
-          self.gdClient.deleteFile(oldestGDFile.clone()).await.expect(format!("Error deleting oldest gd file: {}", oldestGDFile.clone().name.unwrap()).as_str());
-
-          info!("Deleted: {} from google drive to make space for : {}", &oldestGDFile.name.unwrap(), &localFile);
+    let mut gdFileList                      : Vec<google_drive3::api::File> = self.gdClient.getFileListDescending(false).await.unwrap();
+    gdFileList.extend(self.gdClient.getFileListDescending(true).await.unwrap());
+    let clipsFolderID                       : String                        = gdFileList.iter().find(|f| f.name.clone().unwrap() == "ZeroCam Clips").unwrap().id.clone().unwrap();
+    let mut gdClipsNewestFirst              : Vec<google_drive3::api::File> = gdFileList.iter().filter(|f| f.parents.clone().unwrap().contains(&clipsFolderID) && f.name.as_deref() != Some(LEDGER_FILE_NAME)).cloned().collect();
+    gdClipsNewestFirst.sort_by_key(|f| Reverse(driveClipTime(f)));
+    let gdClipNames                         : Vec<String>                   = gdClipsNewestFirst.iter().map(|f| f.name.clone().unwrap()).collect();
+    let localFilesNotInGDNewestFirst        : Vec<(DateTime<Utc>, String)>  = self.getLocalFilesNewestFirst()?.into_iter().filter(|(_, f)| !gdClipNames.contains(&self.driveFileName(f))).collect();
+
+    debug!("Local file list not in GD{:?}", &localFilesNotInGDNewestFirst);
+
+    'localFiles: for (localTime, localFile) in &localFilesNotInGDNewestFirst {
+      let driveFileName = self.driveFileName(localFile);
+      let localFileSize: i64 = fs::metadata(self.clipsPath.clone() + &localFile).unwrap().len() as i64;
+      while localFileSize > self.calculateSpaceAvailable(&clipsFolderID).await? {
+        let oldestUnlocked = gdClipsNewestFirst.iter()
+          .rposition(|f| !ClipCatalog::global().isLocked(&catalogFileName(&f.name.clone().unwrap_or_default())))
+          .ok_or(format!("No unlocked clips left in google drive to delete to make space for {}", localFile))?;
+        if driveClipTime(&gdClipsNewestFirst[oldestUnlocked]) > *localTime {
+          info!("Google drive is full of clips newer than {}, older clips are not uploaded", localFile);
+          break 'localFiles; //the rest are older still
         }
+        let oldestGDFile = gdClipsNewestFirst.remove(oldestUnlocked);
+        self.gdClient.deleteFile(oldestGDFile.clone()).await.expect(format!("Error deleting oldest gd file: {}", oldestGDFile.clone().name.unwrap()).as_str());
 
-        self.gdClient.uploadFile(self.clipsPath.clone() + localFile.clone().as_str(), localFile.clone(), clipsFolderID.clone()).await?;
+        let oldestGDFileName = catalogFileName(&oldestGDFile.name.unwrap_or_default());
+        if let Err(e) = ClipCatalog::global().update(&oldestGDFileName, |c| c.uploadState = UploadState::EvictedFromCloud) {
+          debug!("Catalog not updated for {}: {}", oldestGDFileName, e); //the local copy may already be gone
+        }
+        info!("Deleted: {} from google drive to make space for : {}", &oldestGDFileName, &localFile);
+      }
 
-        info!("Successfully uploaded to googled drive: {}", localFile.clone().as_str());
+      let localPath = self.clipsPath.clone() + localFile.as_str();
+      let (uploadPath, thumbnail) = match driveFileName != *localFile {
+        true  => (self.encryptForUpload(localFile, &driveFileName).await?, None), //a plaintext thumbnail would give the clip away
+        false => (localPath.clone(), fs::read(ClipPreview::previewPath(&ClipPreview::posterFileName(localFile))).ok()),
+      };
+      let upload = self.gdClient.uploadFile(uploadPath.clone(), driveFileName.clone(), clipsFolderID.clone(), thumbnail).await;
+      if uploadPath != localPath {
+        let _ = fs::remove_file(&uploadPath);
+      }
+      let (_, uploaded) = upload?;
+      let catalogUpdate = ClipCatalog::global().update(localFile, |c| {
+        c.uploadState = UploadState::Uploaded;
+        c.driveFileId = uploaded.id.clone();
+      });
+      if let Err(e) = catalogUpdate {
+        warn!("Uploaded {} but could not record it in the catalog: {}", localFile, e);
       }
+      let at = gdClipsNewestFirst.partition_point(|f| driveClipTime(f) > *localTime);
+      gdClipsNewestFirst.insert(at, uploaded); //kept in time order so eviction still finds the oldest
+
+      info!("Successfully uploaded to googled drive: {}", localFile.clone().as_str());
     }
 
     info!("Finished backing up all files");
@@ -73,6 +108,32 @@ impl GDController {
     Ok(())
   }
 
+  /// Replaces the ledger on drive once it has grown, the new copy is uploaded before the old one is
+  /// deleted. It only holds clip names and hashes so it goes up unencrypted
+  async fn uploadLedger(&self) -> Result<(), Box<dyn Error>> {
+    let Ok(localSize) = fs::metadata(LEDGER_PATH).map(|m| m.len() as i64) else {
+      return Ok(()); //no clips recorded yet
+    };
+    let gdFileList: Vec<google_drive3::api::File> = self.gdClient.getFileListDescending(false).await?;
+    let clipsFolderID: String = gdFileList.iter()
+      .find(|f| f.name.as_deref() == Some("ZeroCam Clips"))
+      .and_then(|f| f.id.clone())
+      .ok_or("ZeroCam Clips folder is missing from google drive")?;
+    let gdLedgers: Vec<google_drive3::api::File> = gdFileList.into_iter()
+      .filter(|f| f.name.as_deref() == Some(LEDGER_FILE_NAME) && f.parents.as_ref().is_some_and(|p| p.contains(&clipsFolderID)))
+      .collect();
+    if gdLedgers.iter().any(|f| f.size == Some(localSize)) {
+      return Ok(()); //append only, the same size is the same ledger
+    }
+
+    self.gdClient.uploadFile(LEDGER_PATH.to_string(), LEDGER_FILE_NAME.to_string(), clipsFolderID, None).await?;
+    for gdLedger in gdLedgers {
+      self.gdClient.deleteFile(gdLedger).await?;
+    }
+    info!("Clip ledger backed up to google drive");
+    Ok(())
+  }
+
   async fn calculateSpaceAvailable(&self, clipsFolderId: &String) -> Result<i64, Box<dyn Error>> {
     let mut gdFiles: Vec<google_drive3::api::File> = self.gdClient.getFileListDescending(false).await?;
     gdFiles.extend(self.gdClient.getFileListDescending(true).await?);
@@ -96,16 +157,56 @@ impl GDController {
     Ok(spaceAvailable)
   }
 
-  fn getLocalFilesDescending(&self) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut files: Vec<_> = fs::read_dir(self.clipsPath.clone())?
+  /// Drive gets the encrypted copy of a plaintext local clip, named after it with .age added
+  fn driveFileName(&self, localFile: &str) -> String {
+    match self.configFile.encryption.enabled && !ClipEncryption::isEncrypted(localFile) {
+      true  => ClipEncryption::encryptedFileName(localFile),
+      false => localFile.to_string(),
+    }
+  }
+
+  /// Encrypts a plaintext clip into a hidden file for the upload, the caller removes it afterwards
+  async fn encryptForUpload(&self, localFile: &str, driveFileName: &str) -> Result<String, Box<dyn Error>> {
+    let from = PathBuf::from(self.clipsPath.clone() + localFile);
+    let target = partPath(&PathBuf::from(self.clipsPath.clone() + driveFileName));
+    let to = target.display().to_string();
+    let recipient = self.configFile.encryption.recipient.clone();
+    tokio::task::spawn_blocking(move || ClipEncryption::encryptFile(&from, &target, &recipient).map_err(|e| e.to_string()))
+      .await??;
+    Ok(to)
+  }
+
+  /// Local clips with their trigger time, files the catalog doesn't know go by modification time
+  fn getLocalFilesNewestFirst(&self) -> Result<Vec<(DateTime<Utc>, String)>, Box<dyn Error>> {
+    let mut files: Vec<(DateTime<Utc>, String)> = fs::read_dir(self.clipsPath.clone())?
       .filter_map(|e| {
         let entry = e.ok()?;
-        let meta = entry.metadata().ok()?;
-        let modified = meta.modified().ok()?;
-        Some((modified, entry.file_name().into_string().unwrap()))
+        let name = entry.file_name().into_string().ok()?;
+        let time = match ClipCatalog::global().get(&name) {
+          Ok(Some(clip)) => clip.triggerTime,
+          _              => entry.metadata().ok()?.modified().ok()?.into(),
+        };
+        Some((time, name))
       })
+      .filter(|(_, name)| !name.starts_with('.')) //.gitkeep and files still being moved in
       .collect();
     files.sort_by_key(|(time, _)| Reverse(*time));
-    Ok(files.into_iter().map(|(_, name)| name).collect())
+    Ok(files)
+  }
+}
+
+/// The catalog knows a clip by its local name, which lacks the .age when only the upload is encrypted
+fn catalogFileName(driveFileName: &str) -> String {
+  match ClipCatalog::global().get(driveFileName) {
+    Ok(Some(_)) => driveFileName.to_string(),
+    _           => ClipEncryption::plainFileName(driveFileName).to_string(),
+  }
+}
+
+/// When a drive clip was triggered, from the catalog or else when it was uploaded
+fn driveClipTime(file: &google_drive3::api::File) -> DateTime<Utc> {
+  match ClipCatalog::global().get(&catalogFileName(&file.name.clone().unwrap_or_default())) {
+    Ok(Some(clip)) => clip.triggerTime,
+    _              => file.created_time.unwrap_or(DateTime::<Utc>::MIN_UTC),
   }
 }
@@ -0,0 +1,267 @@
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF    : Duration = Duration::from_secs(60);
+const STABLE_RUN     : Duration = Duration::from_secs(60); // a child that ran this long gets its backoff reset
+const SHUTDOWN_GRACE : Duration = Duration::from_secs(10); // between SIGTERM and kill, ffmpeg closes the segment it is writing
+const KILL_WAIT      : Duration = Duration::from_secs(5);
+
+static SUPERVISOR: OnceLock<Supervisor> = OnceLock::new();
+
+/// What to run and whether to bring it back when it exits
+#[derive(Debug, Clone)]
+pub struct ChildSpec {
+  pub name   : String,
+  pub program: String,
+  pub args   : Vec<String>,
+  pub env    : Vec<(String, String)>, // added to the inherited environment
+  pub stdout : Option<StdoutReader>, // logged when missing
+  pub restart: bool, // false for one shot setup commands
+}
+
+/// Takes over a child's stdout instead of it being logged, called again after every restart
+#[derive(Clone)]
+pub struct StdoutReader(pub Arc<dyn Fn(ChildStdout) + Send + Sync>);
+
+impl fmt::Debug for StdoutReader {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("StdoutReader")
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ProcessState {
+  Starting,
+  Running,
+  Backoff,
+  Exited,
+  Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessHealth {
+  pub name         : String,
+  pub state        : ProcessState,
+  pub pid          : Option<u32>,
+  pub restarts     : u32,
+  pub lastExit     : Option<String>,
+  pub lastStartedAt: Option<i64>, // unix seconds
+}
+
+/// Owns every child process the app starts, restarts the ones that die with exponential backoff
+/// and forwards their output into the log
+pub struct Supervisor {
+  health  : Arc<Mutex<BTreeMap<String, ProcessHealth>>>,
+  children: Mutex<BTreeMap<String, ChildTask>>,
+}
+
+struct ChildTask {
+  stop: watch::Sender<bool>,
+  task: JoinHandle<()>,
+}
+
+impl Supervisor {
+  pub fn global() -> &'static Supervisor {
+    SUPERVISOR.get_or_init(|| {
+      Supervisor {
+        health  : Arc::new(Mutex::new(BTreeMap::new())),
+        children: Mutex::new(BTreeMap::new()),
+      }
+    })
+  }
+
+  pub fn supervise(&self, spec: ChildSpec) {
+    self.setHealth(&spec.name, |h| h.state = ProcessState::Starting);
+    let health     = self.health.clone();
+    let name       = spec.name.clone();
+    let (stop, rx) = watch::channel(false);
+    let task = tokio::spawn(async move {
+      runChild(spec, health, rx).await;
+    });
+    if let Some(previous) = self.children.lock().unwrap().insert(name.clone(), ChildTask { stop: stop, task: task }) {
+      error!("{}: supervised twice, the previous instance is no longer tracked", name);
+      previous.stop.send_replace(true);
+    }
+  }
+
+  /// Stops the named child and starts it again from a new spec, eg. with different arguments
+  pub async fn replace(&self, spec: ChildSpec) {
+    self.stop(&spec.name).await;
+    self.supervise(spec);
+  }
+
+  /// Stops the named child without restarting it
+  pub async fn stop(&self, name: &str) {
+    let child = self.children.lock().unwrap().remove(name);
+    if let Some(child) = child {
+      stopChild(name, child).await;
+    }
+  }
+
+  pub fn health(&self) -> Vec<ProcessHealth> {
+    self.health.lock().unwrap().values().cloned().collect()
+  }
+
+  /// Stops every child and waits for their tasks to finish
+  pub async fn shutdown(&self) {
+    info!("Stopping all child processes");
+    let children: Vec<(String, ChildTask)> = std::mem::take(&mut *self.children.lock().unwrap()).into_iter().collect();
+    for (_, child) in &children {
+      child.stop.send_replace(true);
+    }
+    for (name, child) in children {
+      stopChild(&name, child).await;
+    }
+    info!("All child processes stopped");
+  }
+
+  fn setHealth(&self, name: &str, update: impl FnOnce(&mut ProcessHealth)) {
+    updateHealth(&self.health, name, update);
+  }
+}
+
+async fn runChild(spec: ChildSpec, health: Arc<Mutex<BTreeMap<String, ProcessHealth>>>, mut shutdown: watch::Receiver<bool>) {
+  let mut backoff = INITIAL_BACKOFF;
+  loop {
+    if *shutdown.borrow() { break; }
+    updateHealth(&health, &spec.name, |h| h.state = ProcessState::Starting);
+
+    let spawned = Command::new(&spec.program)
+      .args(&spec.args)
+      .envs(spec.env.iter().cloned())
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .kill_on_drop(true)
+      .spawn();
+
+    let mut child = match spawned {
+      Ok(child) => child,
+      Err(e) => {
+        error!("{}: failed to start {}: {}", spec.name, spec.program, e);
+        updateHealth(&health, &spec.name, |h| {
+          h.state    = ProcessState::Backoff;
+          h.lastExit = Some(format!("failed to start: {}", e));
+        });
+        if !spec.restart || waitOrShutdown(backoff, &mut shutdown).await { break; }
+        backoff = min(backoff * 2, MAX_BACKOFF);
+        continue;
+      }
+    };
+
+    let startedAt = Instant::now();
+    info!("{}: started with pid {}", spec.name, child.id().unwrap_or_default());
+    updateHealth(&health, &spec.name, |h| {
+      h.state         = ProcessState::Running;
+      h.pid           = child.id();
+      h.lastStartedAt = Some(Utc::now().timestamp());
+    });
+    match (child.stdout.take(), &spec.stdout) {
+      (Some(stdout), Some(reader)) => (reader.0)(stdout),
+      (Some(stdout), None)         => forwardOutput(spec.name.clone(), stdout, false),
+      (None, _)                    => (),
+    }
+    if let Some(stderr) = child.stderr.take() { forwardOutput(spec.name.clone(), stderr, true); }
+
+    let exit = tokio::select! {
+      status = child.wait() => status.map(|s| s.to_string()).unwrap_or_else(|e| e.to_string()),
+      _ = shutdown.changed() => {
+        terminate(&spec.name, &mut child).await;
+        break;
+      }
+    };
+
+    updateHealth(&health, &spec.name, |h| {
+      h.pid      = None;
+      h.lastExit = Some(exit.clone());
+    });
+    if !spec.restart {
+      info!("{}: exited with {}", spec.name, exit);
+      updateHealth(&health, &spec.name, |h| h.state = ProcessState::Exited);
+      break;
+    }
+
+    if startedAt.elapsed() >= STABLE_RUN { backoff = INITIAL_BACKOFF; }
+    error!("{}: exited with {}, restarting in {}sec", spec.name, exit, backoff.as_secs());
+    updateHealth(&health, &spec.name, |h| {
+      h.state     = ProcessState::Backoff;
+      h.restarts += 1;
+    });
+    if waitOrShutdown(backoff, &mut shutdown).await { break; }
+    backoff = min(backoff * 2, MAX_BACKOFF);
+  }
+
+  updateHealth(&health, &spec.name, |h| {
+    if h.state != ProcessState::Exited { h.state = ProcessState::Stopped; }
+    h.pid = None;
+  });
+}
+
+async fn stopChild(name: &str, child: ChildTask) {
+  child.stop.send_replace(true);
+  if timeout(SHUTDOWN_GRACE + KILL_WAIT, child.task).await.is_err() {
+    error!("{}: did not stop within {}sec", name, (SHUTDOWN_GRACE + KILL_WAIT).as_secs());
+  }
+}
+
+/// Asks the child to exit with SIGTERM so ffmpeg can finish its segment and segment list line,
+/// kills it if it is still running after SHUTDOWN_GRACE
+async fn terminate(name: &str, child: &mut Child) {
+  #[cfg(unix)]
+  if let Some(pid) = child.id() {
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM); }
+    match timeout(SHUTDOWN_GRACE, child.wait()).await {
+      Ok(_)  => {
+        info!("{}: stopped", name);
+        return;
+      }
+      Err(_) => warn!("{}: still running {}sec after SIGTERM, killing", name, SHUTDOWN_GRACE.as_secs()),
+    }
+  }
+  if let Err(e) = child.kill().await { error!("{}: failed to kill: {}", name, e); }
+  info!("{}: stopped", name);
+}
+
+/// Returns true if shutdown was requested while waiting
+async fn waitOrShutdown(duration: Duration, shutdown: &mut watch::Receiver<bool>) -> bool {
+  tokio::select! {
+    _ = sleep(duration) => *shutdown.borrow(),
+    _ = shutdown.changed() => true,
+  }
+}
+
+fn forwardOutput<R: AsyncRead + Unpin + Send + 'static>(name: String, output: R, isStderr: bool) {
+  tokio::spawn(async move {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+      if isStderr { warn!("{}: {}", name, line); }
+      else        { debug!("{}: {}", name, line); }
+    }
+  });
+}
+
+fn updateHealth(health: &Mutex<BTreeMap<String, ProcessHealth>>, name: &str, update: impl FnOnce(&mut ProcessHealth)) {
+  let mut health = health.lock().unwrap();
+  let entry = health.entry(name.to_string()).or_insert_with(|| ProcessHealth {
+    name         : name.to_string(),
+    state        : ProcessState::Starting,
+    pid          : None,
+    restarts     : 0,
+    lastExit     : None,
+    lastStartedAt: None,
+  });
+  update(entry);
+}
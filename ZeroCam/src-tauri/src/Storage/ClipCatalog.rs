@@ -0,0 +1,228 @@
+use crate::Camera::ClipScheduler::TriggerSource;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+const CATALOG_PATH: &str = "../lib/zerocam/clipCatalog.jsonl";
+
+static CLIP_CATALOG: OnceLock<ClipCatalog> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+  Pending,
+  Uploaded,
+  EvictedFromCloud, // uploaded once then deleted from drive to make space
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipRecord {
+  pub fileName       : String,
+  #[serde(default)]
+  pub camera         : Option<String>, // missing for clips adopted from disk
+  pub triggerSource  : TriggerSource,
+  pub triggerTime    : DateTime<Utc>,
+  pub motionPeakScore: Option<f64>,
+  pub durationSec    : f64,
+  pub sizeB          : u64,
+  pub sha256         : String,
+  pub uploadState    : UploadState,
+  pub driveFileId    : Option<String>,
+  pub locked         : bool,
+  pub createdAt      : DateTime<Utc>,
+  #[serde(default)]
+  pub posterFileName : Option<String>, // in the Previews directory
+  #[serde(default)]
+  pub previewFileName: Option<String>,
+}
+
+/// One line of the catalog file, the latest line for a clip wins
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum CatalogLine {
+  Upsert { clip: ClipRecord },
+  Remove { fileName: String },
+}
+
+/// Append only record of every clip on this device, why it was made and where it has been
+/// uploaded, shared by the camera, the backup and the GUI
+pub struct ClipCatalog {
+  clips: Mutex<Option<BTreeMap<String, ClipRecord>>>, // loaded on first use
+}
+
+impl ClipCatalog {
+  pub fn global() -> &'static ClipCatalog {
+    CLIP_CATALOG.get_or_init(|| ClipCatalog {
+      clips: Mutex::new(None),
+    })
+  }
+
+  /// Oldest trigger first
+  pub fn all(&self) -> Result<Vec<ClipRecord>, Box<dyn Error>> {
+    let mut clips: Vec<ClipRecord> = self.withClips(|clips| Ok(clips.values().cloned().collect()))?;
+    clips.sort_by_key(|c| c.triggerTime);
+    Ok(clips)
+  }
+
+  pub fn get(&self, fileName: &str) -> Result<Option<ClipRecord>, Box<dyn Error>> {
+    self.withClips(|clips| Ok(clips.get(fileName).cloned()))
+  }
+
+  pub fn insert(&self, clip: ClipRecord) -> Result<(), Box<dyn Error>> {
+    self.withClips(|clips| {
+      appendLine(&CatalogLine::Upsert { clip: clip.clone() })?;
+      clips.insert(clip.fileName.clone(), clip);
+      Ok(())
+    })
+  }
+
+  pub fn update(&self, fileName: &str, change: impl FnOnce(&mut ClipRecord)) -> Result<ClipRecord, Box<dyn Error>> {
+    self.withClips(|clips| {
+      let clip = clips.get_mut(fileName).ok_or(format!("Clip {} is not in the catalog", fileName))?;
+      change(clip);
+      appendLine(&CatalogLine::Upsert { clip: clip.clone() })?;
+      Ok(clip.clone())
+    })
+  }
+
+  /// Locked clips are never evicted locally or from google drive
+  pub fn setLocked(&self, fileName: &str, locked: bool) -> Result<ClipRecord, Box<dyn Error>> {
+    let clip = self.update(fileName, |c| c.locked = locked)?;
+    info!("Clip {} {}", fileName, if locked { "locked" } else { "unlocked" });
+    Ok(clip)
+  }
+
+  pub fn isLocked(&self, fileName: &str) -> bool {
+    self.get(fileName).ok().flatten().map(|c| c.locked).unwrap_or(false)
+  }
+
+  pub fn newest(&self) -> Result<Option<ClipRecord>, Box<dyn Error>> {
+    Ok(self.all()?.pop())
+  }
+
+  pub fn remove(&self, fileName: &str) -> Result<(), Box<dyn Error>> {
+    self.withClips(|clips| {
+      if clips.remove(fileName).is_some() {
+        appendLine(&CatalogLine::Remove { fileName: fileName.to_string() })?;
+      }
+      Ok(())
+    })
+  }
+
+  /// Drops records whose file has gone and adopts clips that were made before the catalog existed
+  pub fn reconcile(&self, clipsPath: &Path) -> Result<(), Box<dyn Error>> {
+    let onDisk: Vec<(String, fs::Metadata)> = fs::read_dir(clipsPath)?
+      .filter_map(|e| {
+        let entry = e.ok()?;
+        let meta = entry.metadata().ok()?;
+        Some((entry.file_name().into_string().ok()?, meta))
+      })
+      .filter(|(name, meta)| meta.is_file() && !name.starts_with('.'))
+      .collect();
+
+    for clip in self.all()? {
+      if !onDisk.iter().any(|(name, _)| name == &clip.fileName) {
+        info!("Catalog: {} no longer exists, removing", clip.fileName);
+        self.remove(&clip.fileName)?;
+      }
+    }
+
+    for (name, meta) in onDisk {
+      if self.get(&name)?.is_some() { continue; }
+      let modified: DateTime<Utc> = meta.modified()?.into();
+      info!("Catalog: adopting untracked clip {}", name);
+      self.insert(ClipRecord {
+        fileName       : name.clone(),
+        camera         : None,
+        triggerSource  : TriggerSource::Unknown,
+        triggerTime    : modified,
+        motionPeakScore: None,
+        durationSec    : 0.0,
+        sizeB          : meta.len(),
+        sha256         : sha256File(&clipsPath.join(&name))?,
+        uploadState    : UploadState::Pending,
+        driveFileId    : None,
+        locked         : false,
+        createdAt      : modified,
+        posterFileName : None,
+        previewFileName: None,
+      })?;
+    }
+    Ok(())
+  }
+
+  fn withClips<T>(&self, action: impl FnOnce(&mut BTreeMap<String, ClipRecord>) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    let mut guard = self.clips.lock().unwrap();
+    if guard.is_none() {
+      *guard = Some(load()?);
+    }
+    action(guard.as_mut().unwrap())
+  }
+}
+
+pub fn sha256File(path: &Path) -> Result<String, Box<dyn Error>> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; 1024 * 1024];
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 { break; }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn load() -> Result<BTreeMap<String, ClipRecord>, Box<dyn Error>> {
+  let mut clips: BTreeMap<String, ClipRecord> = BTreeMap::new();
+  let file = match fs::File::open(CATALOG_PATH) {
+    Ok(file) => file,
+    Err(_)   => return Ok(clips), //first run
+  };
+
+  let mut lineCount = 0;
+  for line in BufReader::new(file).lines() {
+    let line = line?;
+    if line.trim().is_empty() { continue; }
+    lineCount += 1;
+    match serde_json::from_str::<CatalogLine>(&line) {
+      Ok(CatalogLine::Upsert { clip })     => { clips.insert(clip.fileName.clone(), clip); }
+      Ok(CatalogLine::Remove { fileName }) => { clips.remove(&fileName); }
+      Err(e) => warn!("Catalog: skipping unreadable line: {}", e), //a torn write from a power cut
+    }
+  }
+
+  if lineCount > clips.len() * 2 + 100 {
+    compact(&clips)?;
+  }
+  info!("Catalog loaded with {} clips", clips.len());
+  Ok(clips)
+}
+
+/// Rewrites the catalog with one line per clip, swapped in with a rename so a power cut leaves
+/// either the old or the new file
+fn compact(clips: &BTreeMap<String, ClipRecord>) -> Result<(), Box<dyn Error>> {
+  let tempPath = format!("{}.tmp", CATALOG_PATH);
+  let mut temp = fs::File::create(&tempPath)?;
+  for clip in clips.values() {
+    writeln!(temp, "{}", serde_json::to_string(&CatalogLine::Upsert { clip: clip.clone() })?)?;
+  }
+  temp.sync_all()?;
+  fs::rename(&tempPath, CATALOG_PATH)?;
+  info!("Catalog compacted");
+  Ok(())
+}
+
+fn appendLine(line: &CatalogLine) -> Result<(), Box<dyn Error>> {
+  let mut file = fs::OpenOptions::new().create(true).append(true).open(CATALOG_PATH)?;
+  writeln!(file, "{}", serde_json::to_string(line)?)?;
+  file.sync_data()?;
+  Ok(())
+}
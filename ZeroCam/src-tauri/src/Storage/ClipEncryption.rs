@@ -0,0 +1,80 @@
+use age::x25519;
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+pub const ENCRYPTED_EXTENSION: &str = "age";
+
+/// Name of the encrypted copy of a clip, eg. front_....mp4.age
+pub fn encryptedFileName(fileName: &str) -> String {
+  format!("{}.{}", fileName, ENCRYPTED_EXTENSION)
+}
+
+pub fn isEncrypted(fileName: &str) -> bool {
+  fileName.ends_with(&format!(".{}", ENCRYPTED_EXTENSION))
+}
+
+/// Name of the clip once decrypted, unchanged if it was never encrypted
+pub fn plainFileName(fileName: &str) -> &str {
+  fileName.strip_suffix(&format!(".{}", ENCRYPTED_EXTENSION)).unwrap_or(fileName)
+}
+
+/// Encrypts from into to for an age public key (age1...), only the holder of the matching secret
+/// key can read it so nothing on the device can. The output is only complete once this returns
+pub fn encryptFile(from: &Path, to: &Path, recipient: &str) -> Result<(), Box<dyn Error>> {
+  let recipient: x25519::Recipient = recipient.trim().parse()
+    .map_err(|e| format!("encryption.recipient is not an age public key: {}", e))?;
+  let encryptor = age::Encryptor::with_recipients(iter::once(&recipient as &dyn age::Recipient))?;
+
+  let mut input = BufReader::new(File::open(from)?);
+  let mut output = encryptor.wrap_output(BufWriter::new(File::create(to)?))?;
+  io::copy(&mut input, &mut output)?;
+  output.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+  Ok(())
+}
+
+/// Decrypts from into to with the secret key (AGE-SECRET-KEY-1...) in an age identity file
+pub fn decryptFile(from: &Path, to: &Path, identityFile: &Path) -> Result<(), Box<dyn Error>> {
+  let identity = readIdentity(identityFile)?;
+  let decryptor = age::Decryptor::new(BufReader::new(File::open(from)?))?;
+  let mut input = decryptor.decrypt(iter::once(&identity as &dyn age::Identity))?;
+
+  let mut output = BufWriter::new(File::create(to)?);
+  io::copy(&mut input, &mut output)?;
+  output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+  Ok(())
+}
+
+/// `ZeroCam decrypt <identity file> <clip.age>...` writes each clip next to itself without the .age
+pub fn decryptCommand(args: &[String]) -> Result<(), Box<dyn Error>> {
+  let (identityFile, clips) = match args {
+    [identityFile, clips @ ..] if !clips.is_empty() => (Path::new(identityFile), clips),
+    _ => return Err("Usage: ZeroCam decrypt <identity file> <clip.age>...".into()),
+  };
+  for clip in clips {
+    let from = PathBuf::from(clip);
+    let fileName = from.file_name().ok_or(format!("{} is not a file", clip))?.to_string_lossy().to_string();
+    if !isEncrypted(&fileName) {
+      return Err(format!("{} does not end in .{}", clip, ENCRYPTED_EXTENSION).into());
+    }
+    let to = from.with_file_name(plainFileName(&fileName));
+    decryptFile(&from, &to, identityFile).map_err(|e| format!("Failed to decrypt {}: {}", clip, e))?;
+    println!("Decrypted {}", to.display());
+  }
+  Ok(())
+}
+
+/// Identity files may hold comments, eg. the public key, besides the secret key line
+fn readIdentity(identityFile: &Path) -> Result<x25519::Identity, Box<dyn Error>> {
+  let contents = fs::read_to_string(identityFile)
+    .map_err(|e| format!("Cannot read identity file {}: {}", identityFile.display(), e))?;
+  let secretKey = contents.lines()
+    .map(str::trim)
+    .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+    .ok_or(format!("{} holds no AGE-SECRET-KEY", identityFile.display()))?;
+  Ok(secretKey.parse().map_err(|e| format!("Invalid secret key in {}: {}", identityFile.display(), e))?)
+}
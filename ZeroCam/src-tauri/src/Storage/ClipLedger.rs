@@ -0,0 +1,335 @@
+use crate::Storage::ClipCatalog::sha256File;
+use crate::Storage::ClipEncryption;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{info, warn};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+pub const LEDGER_PATH     : &str = "../lib/zerocam/clipLedger.jsonl";
+pub const LEDGER_FILE_NAME: &str = "clipLedger.jsonl"; // on google drive, beside the clips
+const DEVICE_KEY_PATH       : &str = "../lib/zerocam/ledgerKey";
+const DEVICE_PUBLIC_KEY_PATH: &str = "../lib/zerocam/ledgerKey.pub";
+const GENESIS_HASH          : &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+static CLIP_LEDGER: OnceLock<ClipLedger> = OnceLock::new();
+
+/// One line of the ledger. The hash covers the entry and the hash of the entry before it, so no
+/// entry can be changed, removed or reordered without breaking every hash after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+  pub index       : u64,
+  pub fileName    : String, // the plaintext clip, also when it is stored or uploaded encrypted
+  pub sha256      : String,
+  pub sizeB       : u64,
+  pub recordedAt  : DateTime<Utc>,
+  pub previousHash: String,
+  pub hash        : String,
+  #[serde(default)]
+  pub signature   : Option<String>, // of hash by the device key, while ledger.sign is on
+}
+
+struct ChainHead {
+  nextIndex: u64,
+  hash     : String,
+}
+
+/// Append only, hash chained record of the SHA-256 of every clip this device made, the proof
+/// that a clip is unchanged since it was recorded. Entries are never removed, not even when the
+/// clip is
+pub struct ClipLedger {
+  head: Mutex<Option<ChainHead>>, // loaded on first use
+}
+
+impl ClipLedger {
+  pub fn global() -> &'static ClipLedger {
+    CLIP_LEDGER.get_or_init(|| ClipLedger {
+      head: Mutex::new(None),
+    })
+  }
+
+  /// Chains a finished clip onto the ledger, the entry is on disk before this returns
+  pub fn append(&self, fileName: &str, sha256: &str, sizeB: u64, sign: bool) -> Result<LedgerEntry, Box<dyn Error>> {
+    let mut guard = self.head.lock().unwrap();
+    if guard.is_none() {
+      *guard = Some(loadHead()?);
+    }
+    let head = guard.as_mut().unwrap();
+
+    let mut entry = LedgerEntry {
+      index       : head.nextIndex,
+      fileName    : fileName.to_string(),
+      sha256      : sha256.to_string(),
+      sizeB       : sizeB,
+      recordedAt  : Utc::now(),
+      previousHash: head.hash.clone(),
+      hash        : String::new(),
+      signature   : None,
+    };
+    entry.hash = entryHash(&entry);
+    if sign {
+      entry.signature = Some(hex::encode(deviceKey()?.sign(entry.hash.as_bytes()).to_bytes()));
+    }
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(LEDGER_PATH)?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    file.sync_data()?;
+    head.nextIndex = entry.index + 1;
+    head.hash      = entry.hash.clone();
+    Ok(entry)
+  }
+}
+
+/// `ZeroCam verify [--ledger <clipLedger.jsonl>] [--key <ledgerKey.pub>] <clip>...` checks the whole
+/// chain, then that each clip's SHA-256 was recorded. Clips downloaded from drive still match when
+/// renamed, encrypted ones have to be decrypted first
+pub fn verifyCommand(args: &[String]) -> Result<(), Box<dyn Error>> {
+  let usage = "Usage: ZeroCam verify [--ledger <clipLedger.jsonl>] [--key <ledgerKey.pub>] <clip>...";
+  let mut ledgerPath = LEDGER_PATH.to_string();
+  let mut keyPath: Option<String> = None;
+  let mut clips: Vec<&String> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--ledger" => ledgerPath = args.next().ok_or(usage)?.clone(),
+      "--key"    => keyPath = Some(args.next().ok_or(usage)?.clone()),
+      _          => clips.push(arg),
+    }
+  }
+  if clips.is_empty() {
+    return Err(usage.into());
+  }
+  let keyPath = keyPath.or(Path::new(DEVICE_PUBLIC_KEY_PATH).exists().then(|| DEVICE_PUBLIC_KEY_PATH.to_string()));
+  let publicKey = keyPath.as_deref().map(|path| readPublicKey(Path::new(path))).transpose()?;
+
+  let (entries, mut problems) = readChain(Path::new(&ledgerPath), publicKey.as_ref())?;
+  let signed = entries.iter().filter(|e| e.signature.is_some()).count();
+  match (&publicKey, signed) {
+    (None, 1..) => problems.push(format!("{} entries are signed but no public key was given with --key", signed)),
+    (Some(_), _) => println!("{} of {} entries signed by the device key", signed, entries.len()),
+    _ => {}
+  }
+  for problem in &problems {
+    println!("LEDGER {}", problem);
+  }
+
+  let mut failed = !problems.is_empty();
+  for clip in clips {
+    let path = Path::new(clip);
+    if ClipEncryption::isEncrypted(clip) {
+      println!("SKIPPED {}: decrypt it first, the ledger records the plaintext", clip);
+      failed = true;
+      continue;
+    }
+    let sha256 = sha256File(path).map_err(|e| format!("Cannot read {}: {}", clip, e))?;
+    let fileName = path.file_name().unwrap_or_default().to_string_lossy();
+    match (entries.iter().find(|e| e.sha256 == sha256), entries.iter().find(|e| e.fileName == fileName)) {
+      (Some(entry), _) => println!("OK {}: entry {} {} recorded {}", clip, entry.index, entry.fileName, entry.recordedAt),
+      (None, Some(entry)) => {
+        println!("MODIFIED {}: differs from entry {} recorded {}", clip, entry.index, entry.recordedAt);
+        failed = true;
+      }
+      (None, None) => {
+        println!("UNKNOWN {}: not in the ledger", clip);
+        failed = true;
+      }
+    }
+  }
+  match failed {
+    true  => Err("Verification failed".into()),
+    false => Ok(()),
+  }
+}
+
+/// Every entry in the ledger file with what is wrong with the chain, a torn line from a power cut
+/// holds no entry so it is skipped, an entry cut out of the middle still breaks the links
+pub fn readChain(ledgerPath: &Path, publicKey: Option<&VerifyingKey>) -> Result<(Vec<LedgerEntry>, Vec<String>), Box<dyn Error>> {
+  let file = fs::File::open(ledgerPath).map_err(|e| format!("Cannot read ledger {}: {}", ledgerPath.display(), e))?;
+  let mut entries: Vec<LedgerEntry> = Vec::new();
+  let mut problems: Vec<String> = Vec::new();
+  let mut previousHash = GENESIS_HASH.to_string();
+  let mut nextIndex: u64 = 0;
+
+  for (lineNumber, line) in BufReader::new(file).lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() { continue; }
+    let entry: LedgerEntry = match serde_json::from_str(&line) {
+      Ok(entry) => entry,
+      Err(e)    => {
+        warn!("Ledger: skipping unreadable line {}: {}", lineNumber + 1, e);
+        continue;
+      }
+    };
+    if entry.index != nextIndex {
+      problems.push(format!("entry {} found where entry {} should be, entries were removed or reordered", entry.index, nextIndex));
+    }
+    if entry.previousHash != previousHash {
+      problems.push(format!("entry {} does not link to the entry before it", entry.index));
+    }
+    if entryHash(&entry) != entry.hash {
+      problems.push(format!("entry {} ({}) was altered", entry.index, entry.fileName));
+    }
+    if let (Some(signature), Some(publicKey)) = (&entry.signature, publicKey) {
+      if !signatureValid(publicKey, &entry.hash, signature) {
+        problems.push(format!("entry {} has an invalid signature", entry.index));
+      }
+    }
+    previousHash = entry.hash.clone();
+    nextIndex    = entry.index + 1;
+    entries.push(entry);
+  }
+  Ok((entries, problems))
+}
+
+fn loadHead() -> Result<ChainHead, Box<dyn Error>> {
+  if !Path::new(LEDGER_PATH).exists() {
+    return Ok(ChainHead { nextIndex: 0, hash: GENESIS_HASH.to_string() }); //first run
+  }
+  let (entries, problems) = readChain(Path::new(LEDGER_PATH), None)?;
+  for problem in problems {
+    warn!("Ledger: {}", problem);
+  }
+  info!("Ledger loaded with {} entries", entries.len());
+  Ok(match entries.last() {
+    Some(last) => ChainHead { nextIndex: last.index + 1, hash: last.hash.clone() },
+    None       => ChainHead { nextIndex: 0, hash: GENESIS_HASH.to_string() },
+  })
+}
+
+fn entryHash(entry: &LedgerEntry) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(format!("{}|{}|{}|{}|{}|{}",
+    entry.index, entry.fileName, entry.sha256, entry.sizeB,
+    entry.recordedAt.to_rfc3339_opts(SecondsFormat::Nanos, true), entry.previousHash));
+  format!("{:x}", hasher.finalize())
+}
+
+/// The device's signing key, made on first use with its public key written beside it for verify
+fn deviceKey() -> Result<SigningKey, Box<dyn Error>> {
+  if let Ok(seed) = fs::read_to_string(DEVICE_KEY_PATH) {
+    let seed: [u8; 32] = hex::decode(seed.trim())?.try_into().map_err(|_| format!("{} is not a 32 byte key", DEVICE_KEY_PATH))?;
+    return Ok(SigningKey::from_bytes(&seed));
+  }
+  let key = SigningKey::generate(&mut OsRng);
+  fs::OpenOptions::new().write(true).create_new(true).mode(0o600) //only the device may sign, never overwrites an unreadable key
+    .open(DEVICE_KEY_PATH)?
+    .write_all(hex::encode(key.to_bytes()).as_bytes())?;
+  fs::write(DEVICE_PUBLIC_KEY_PATH, hex::encode(key.verifying_key().to_bytes()))?;
+  info!("Ledger: created device key, public key {}", hex::encode(key.verifying_key().to_bytes()));
+  Ok(key)
+}
+
+fn readPublicKey(path: &Path) -> Result<VerifyingKey, Box<dyn Error>> {
+  let contents = fs::read_to_string(path).map_err(|e| format!("Cannot read public key {}: {}", path.display(), e))?;
+  let bytes: [u8; 32] = hex::decode(contents.trim())?.try_into().map_err(|_| format!("{} is not a 32 byte public key", path.display()))?;
+  Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+fn signatureValid(publicKey: &VerifyingKey, hash: &str, signature: &str) -> bool {
+  let Ok(bytes) = hex::decode(signature) else { return false };
+  let Ok(signature) = Signature::from_slice(&bytes) else { return false };
+  publicKey.verify(hash.as_bytes(), &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chain(fileNames: &[&str], key: Option<&SigningKey>) -> Vec<LedgerEntry> {
+    let mut entries: Vec<LedgerEntry> = Vec::new();
+    for (index, fileName) in fileNames.iter().enumerate() {
+      let mut entry = LedgerEntry {
+        index       : index as u64,
+        fileName    : fileName.to_string(),
+        sha256      : format!("{:x}", Sha256::digest(fileName.as_bytes())),
+        sizeB       : 1000 + index as u64,
+        recordedAt  : Utc::now(),
+        previousHash: entries.last().map(|e| e.hash.clone()).unwrap_or(GENESIS_HASH.to_string()),
+        hash        : String::new(),
+        signature   : None,
+      };
+      entry.hash = entryHash(&entry);
+      entry.signature = key.map(|k| hex::encode(k.sign(entry.hash.as_bytes()).to_bytes()));
+      entries.push(entry);
+    }
+    entries
+  }
+
+  /// Writes the lines to a ledger file of its own and reads the chain back
+  fn readLines(test: &str, lines: &[String], publicKey: Option<&VerifyingKey>) -> (Vec<LedgerEntry>, Vec<String>) {
+    let path = std::env::temp_dir().join(format!("zerocam-ledger-{}-{}.jsonl", test, std::process::id()));
+    fs::write(&path, lines.join("\n") + "\n").unwrap();
+    let chain = readChain(&path, publicKey).unwrap();
+    let _ = fs::remove_file(&path);
+    chain
+  }
+
+  fn lines(entries: &[LedgerEntry]) -> Vec<String> {
+    entries.iter().map(|e| serde_json::to_string(e).unwrap()).collect()
+  }
+
+  #[test]
+  fn acceptsAnIntactChain() {
+    let (entries, problems) = readLines("intact", &lines(&chain(&["a.mp4", "b.mp4", "c.mp4"], None)), None);
+    assert_eq!(entries.len(), 3);
+    assert!(problems.is_empty(), "{:?}", problems);
+  }
+
+  #[test]
+  fn detectsAnAlteredEntry() {
+    let mut entries = chain(&["a.mp4", "b.mp4", "c.mp4"], None);
+    entries[1].sha256 = format!("{:x}", Sha256::digest(b"edited"));
+    let (_, problems) = readLines("altered", &lines(&entries), None);
+    assert_eq!(problems, ["entry 1 (b.mp4) was altered"]);
+  }
+
+  #[test]
+  fn detectsARemovedEntry() {
+    let mut entries = chain(&["a.mp4", "b.mp4", "c.mp4"], None);
+    entries.remove(1);
+    let (_, problems) = readLines("removed", &lines(&entries), None);
+    assert_eq!(problems, [
+      "entry 2 found where entry 1 should be, entries were removed or reordered",
+      "entry 2 does not link to the entry before it",
+    ]);
+  }
+
+  #[test]
+  fn detectsAnEntryRehashedAfterEditing() {
+    let mut entries = chain(&["a.mp4", "b.mp4", "c.mp4"], None);
+    entries[1].sizeB = 1;
+    entries[1].hash = entryHash(&entries[1]);
+    let (_, problems) = readLines("rehashed", &lines(&entries), None);
+    assert_eq!(problems, ["entry 2 does not link to the entry before it"]);
+  }
+
+  #[test]
+  fn skipsATornLastLine() {
+    let mut entryLines = lines(&chain(&["a.mp4", "b.mp4"], None));
+    let torn = entryLines[1][..20].to_string();
+    entryLines[1] = torn;
+    let (entries, problems) = readLines("torn", &entryLines, None);
+    assert_eq!(entries.len(), 1);
+    assert!(problems.is_empty(), "{:?}", problems);
+  }
+
+  #[test]
+  fn checksSignaturesAgainstThePublicKey() {
+    let key = SigningKey::generate(&mut OsRng);
+    let other = SigningKey::generate(&mut OsRng);
+    let entryLines = lines(&chain(&["a.mp4", "b.mp4"], Some(&key)));
+
+    let (_, problems) = readLines("signed", &entryLines, Some(&key.verifying_key()));
+    assert!(problems.is_empty(), "{:?}", problems);
+    let (_, problems) = readLines("forged", &entryLines, Some(&other.verifying_key()));
+    assert_eq!(problems, ["entry 0 has an invalid signature", "entry 1 has an invalid signature"]);
+  }
+}
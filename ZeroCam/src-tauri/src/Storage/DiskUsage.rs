@@ -0,0 +1,82 @@
+use crate::Storage::StorageRoot::StorageRoot;
+
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use sysinfo::Disks;
+
+/// Space on the filesystem holding a directory and how much of it the directory uses
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeUsage {
+  pub path       : String,
+  pub mountPoint : String,
+  pub fileSystem : String,
+  pub totalB     : u64,
+  pub availableB : u64,
+  pub directoryB : u64, // bytes taken by the files under path
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStatus {
+  pub clips        : VolumeUsage,
+  pub liveRecording: VolumeUsage,
+  pub sameVolume   : bool, // when true both directories share availableB
+  pub usingFallback: bool, // the configured storage root is missing or read only
+}
+
+/// The filesystem a directory lives on is the mounted disk with the longest mount point that is
+/// a prefix of its canonical path, so a usb stick mounted under / is picked over the root disk
+pub fn volumeUsage(path: &Path) -> Result<VolumeUsage, Box<dyn Error>> {
+  let canonical: PathBuf = fs::canonicalize(path)
+    .map_err(|e| format!("Cannot resolve {}: {}", path.display(), e))?;
+  let disks = Disks::new_with_refreshed_list();
+  let disk = disks.list().iter()
+    .filter(|d| canonical.starts_with(d.mount_point()))
+    .max_by_key(|d| d.mount_point().as_os_str().len())
+    .ok_or(format!("No mounted disk contains {}", canonical.display()))?;
+
+  Ok(VolumeUsage {
+    path      : canonical.display().to_string(),
+    mountPoint: disk.mount_point().display().to_string(),
+    fileSystem: disk.file_system().to_string_lossy().to_string(),
+    totalB    : disk.total_space(),
+    availableB: disk.available_space(),
+    directoryB: directorySize(&canonical),
+  })
+}
+
+/// Free space on the filesystem holding path minus the space that must always stay free
+pub fn safeSpaceB(path: &Path, reservedB: u64) -> Result<i64, Box<dyn Error>> {
+  Ok(volumeUsage(path)?.availableB as i64 - reservedB as i64)
+}
+
+/// Status of the clips and LiveRecording directories under the storage root in use
+pub fn currentStorageStatus() -> Result<StorageStatus, Box<dyn Error>> {
+  let root = StorageRoot::global();
+  let mut status = storageStatus(&root.clipsPath(), &root.liveRecordingPath())?;
+  status.usingFallback = root.usingFallback();
+  Ok(status)
+}
+
+pub fn storageStatus(clipsPath: &Path, liveRecordingPath: &Path) -> Result<StorageStatus, Box<dyn Error>> {
+  let clips         = volumeUsage(clipsPath)?;
+  let liveRecording = volumeUsage(liveRecordingPath)?;
+  Ok(StorageStatus {
+    sameVolume   : clips.mountPoint == liveRecording.mountPoint,
+    usingFallback: false,
+    clips        : clips,
+    liveRecording: liveRecording,
+  })
+}
+
+/// Includes subdirectories as LiveRecording holds one directory per camera
+pub fn directorySize(path: &Path) -> u64 {
+  fs::read_dir(path)
+    .map(|entries| entries
+      .filter_map(Result::ok)
+      .filter_map(|e| Some((e.path(), e.metadata().ok()?)))
+      .map(|(path, m)| if m.is_dir() { directorySize(&path) } else { m.len() })
+      .sum())
+    .unwrap_or(0)
+}
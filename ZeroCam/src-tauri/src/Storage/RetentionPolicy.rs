@@ -0,0 +1,267 @@
+use crate::Camera::ClipPreview;
+use crate::Camera::ClipScheduler::TriggerSource;
+use crate::Config;
+use crate::Config::ConfigFile;
+use crate::Storage::ClipCatalog::ClipCatalog;
+use crate::Storage::DiskUsage;
+use crate::Storage::StorageRoot::StorageRoot;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::{fs, time};
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// One enforce at a time, so two callers don't both count the same free space as theirs
+static ENFORCING: Mutex<()> = Mutex::new(());
+
+/// A local clip as the policy sees it
+struct LocalClip {
+  fileName     : String,
+  path         : PathBuf,
+  sizeB        : u64,
+  triggerSource: TriggerSource,
+  triggerTime  : DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Eviction {
+  pub fileName: String,
+  pub reason  : String,
+}
+
+/// Decides which local clips to delete, by age, count, total size and free disk space, with
+/// per trigger type overrides so manual clips can outlive motion clips. Locked clips are exempt
+/// and do not count towards any limit
+pub struct RetentionPolicy {
+  config: ConfigFile,
+}
+
+impl RetentionPolicy {
+  pub async fn new() -> Result<RetentionPolicy, Box<dyn Error>> {
+    Ok(Self {
+      config: Config::getConfig().await?,
+    })
+  }
+
+  /// Runs enforce every check_interval_sec in the background
+  pub fn startTimer(self: Arc<Self>) {
+    let interval = time::Duration::from_secs(self.config.retention.check_interval_sec.max(1));
+    tokio::spawn(async move {
+      loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = self.enforce(0).await {
+          error!("Retention check failed: {}", e);
+        }
+      }
+    });
+  }
+
+  /// Deletes every clip the rules no longer allow, then keeps deleting until reserveB bytes fit
+  /// on disk above disk_full_buffer_gb, fails if only locked clips stand in the way
+  pub async fn enforce(self: &Arc<Self>, reserveB: u64) -> Result<Vec<Eviction>, Box<dyn Error>> {
+    let policy = self.clone();
+    Ok(tokio::task::spawn_blocking(move || policy.enforceBlocking(reserveB).map_err(|e| e.to_string())).await??)
+  }
+
+  fn enforceBlocking(&self, reserveB: u64) -> Result<Vec<Eviction>, Box<dyn Error>> {
+    let _enforcing = ENFORCING.lock().unwrap();
+    let retention = &self.config.retention;
+    let now = Utc::now();
+    let mut clips = self.localClips()?;
+    let mut evictions: Vec<Eviction> = Vec::new();
+
+    self.sortForEviction(&mut clips);
+
+    let mut kept: Vec<LocalClip> = Vec::new();
+    for clip in clips {
+      match self.expiry(&clip) {
+        Some(expiry) if expiry < now => {
+          let reason = format!("older than {} days", self.maxAgeDays(clip.triggerSource));
+          evictions.push(self.evict(&clip, reason)?);
+        }
+        _ => kept.push(clip),
+      }
+    }
+
+    let mut perTrigger: HashMap<TriggerSource, usize> = HashMap::new();
+    for clip in &kept { *perTrigger.entry(clip.triggerSource).or_default() += 1; }
+    let mut remaining: Vec<LocalClip> = Vec::new();
+    for clip in kept {
+      let count = perTrigger.get(&clip.triggerSource).copied().unwrap_or(0);
+      let limit = retention.triggers.get(&clip.triggerSource).map(|q| q.max_clips).unwrap_or(0);
+      if limit > 0 && count > limit {
+        let reason = format!("more than {} {:?} clips", limit, clip.triggerSource);
+        evictions.push(self.evict(&clip, reason)?);
+        perTrigger.insert(clip.triggerSource, count - 1);
+      } else {
+        remaining.push(clip);
+      }
+    }
+
+    let mut count: usize = remaining.len();
+    let mut totalB: u64 = remaining.iter().map(|c| c.sizeB).sum::<u64>() + self.stagedB();
+    let mut remaining = remaining.into_iter();
+    let clipsPath = StorageRoot::global().clipsPath();
+    let maxTotalB = (retention.max_total_gb * GB as f64) as u64;
+    let diskFullBufferB = self.config.clip.disk_full_buffer_gb.max(0) as u64 * GB;
+    loop {
+      let reason = if retention.max_clips > 0 && count > retention.max_clips {
+        format!("more than {} clips", retention.max_clips)
+      } else if maxTotalB > 0 && totalB + reserveB > maxTotalB {
+        format!("clips would take more than {}GB", retention.max_total_gb)
+      } else if DiskUsage::safeSpaceB(&clipsPath, diskFullBufferB)? < reserveB as i64 {
+        format!("less than {}GB free space on the clips disk", self.config.clip.disk_full_buffer_gb)
+      } else {
+        break;
+      };
+
+      let clip = remaining.next()
+        .ok_or(format!("Cannot make space, {} but only locked clips are left, unlock some to make space", reason))?;
+      evictions.push(self.evict(&clip, reason)?);
+      count  -= 1;
+      totalB -= clip.sizeB;
+    }
+
+    if !evictions.is_empty() {
+      info!("Retention: evicted {} clips", evictions.len());
+    }
+    Ok(evictions)
+  }
+
+  fn evict(&self, clip: &LocalClip, reason: String) -> Result<Eviction, Box<dyn Error>> {
+    match fs::remove_file(&clip.path) {
+      Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+      _                                         => (), //already gone, still clean up after it
+    }
+    ClipPreview::remove(&clip.fileName);
+    ClipCatalog::global().remove(&clip.fileName)?;
+    info!("Retention: deleted {}, {}", clip.fileName, reason);
+    Ok(Eviction {
+      fileName: clip.fileName.clone(),
+      reason  : reason,
+    })
+  }
+
+  /// Closest to its allowed age goes first, so under pressure motion clips go before manual ones
+  fn sortForEviction(&self, clips: &mut [LocalClip]) {
+    clips.sort_by_key(|c| (self.expiry(c).unwrap_or(DateTime::<Utc>::MAX_UTC), c.triggerTime));
+  }
+
+  fn maxAgeDays(&self, source: TriggerSource) -> u64 {
+    match self.config.retention.triggers.get(&source) {
+      Some(quota) if quota.max_age_days > 0 => quota.max_age_days,
+      _                                     => self.config.retention.max_age_days,
+    }
+  }
+
+  fn expiry(&self, clip: &LocalClip) -> Option<DateTime<Utc>> {
+    match self.maxAgeDays(clip.triggerSource) {
+      0    => None,
+      days => Some(clip.triggerTime + TimeDelta::days(days as i64)),
+    }
+  }
+
+  /// Timelapse frames and archive segments waiting in hidden directories beside the clips, they
+  /// can't be evicted but take up the same space
+  fn stagedB(&self) -> u64 {
+    fs::read_dir(StorageRoot::global().clipsPath())
+      .map(|entries| entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_str().is_some_and(|n| n.starts_with(".timelapse-") || n.starts_with(".archive-")))
+        .map(|e| DiskUsage::directorySize(&e.path()))
+        .sum())
+      .unwrap_or(0)
+  }
+
+  /// Unlocked clips on disk, described by the catalog where it knows them
+  fn localClips(&self) -> Result<Vec<LocalClip>, Box<dyn Error>> {
+    let catalog = ClipCatalog::global();
+    let mut clips: Vec<LocalClip> = Vec::new();
+    for entry in fs::read_dir(StorageRoot::global().clipsPath())?.filter_map(Result::ok) {
+      let fileName = entry.file_name().to_string_lossy().to_string();
+      let meta = entry.metadata()?;
+      if !meta.is_file() || fileName.starts_with('.') { continue; }
+
+      let record = catalog.get(&fileName)?;
+      if record.as_ref().map(|r| r.locked).unwrap_or(false) { continue; }
+      clips.push(LocalClip {
+        path         : entry.path(),
+        sizeB        : meta.len(),
+        triggerSource: record.as_ref().map(|r| r.triggerSource).unwrap_or(TriggerSource::Unknown),
+        triggerTime  : record.as_ref().map(|r| r.triggerTime).unwrap_or(meta.modified()?.into()),
+        fileName     : fileName,
+      });
+    }
+    Ok(clips)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Config::{testConfig, TriggerQuota};
+
+  fn policy() -> RetentionPolicy {
+    let mut config = testConfig();
+    config.retention.max_age_days = 30;
+    config.retention.triggers = HashMap::from([
+      (TriggerSource::Motion  , TriggerQuota { max_age_days: 7 , max_clips: 200 }),
+      (TriggerSource::Gui     , TriggerQuota { max_age_days: 90, max_clips: 0   }),
+      (TriggerSource::Telegram, TriggerQuota { max_age_days: 90, max_clips: 0   }),
+    ]);
+    RetentionPolicy { config: config }
+  }
+
+  fn clip(fileName: &str, triggerSource: TriggerSource, ageDays: i64) -> LocalClip {
+    LocalClip {
+      fileName     : fileName.to_string(),
+      path         : PathBuf::from(fileName),
+      sizeB        : 0,
+      triggerSource: triggerSource,
+      triggerTime  : Utc::now() - TimeDelta::days(ageDays),
+    }
+  }
+
+  #[test]
+  fn usesTheTriggerMaxAgeOverTheGlobalOne() {
+    let policy = policy();
+    assert_eq!(policy.maxAgeDays(TriggerSource::Motion), 7);
+    assert_eq!(policy.maxAgeDays(TriggerSource::Gui), 90);
+    assert_eq!(policy.maxAgeDays(TriggerSource::Unknown), 30);
+  }
+
+  #[test]
+  fn evictsClosestToExpiryFirst() {
+    let mut clips = vec![
+      clip("gui_old", TriggerSource::Gui, 60),          // expires in 30 days
+      clip("unknown", TriggerSource::Unknown, 1),       // expires in 29 days
+      clip("motion_new", TriggerSource::Motion, 1),     // expires in 6 days
+      clip("motion_old", TriggerSource::Motion, 5),     // expires in 2 days
+      clip("telegram_new", TriggerSource::Telegram, 0), // expires in 90 days
+    ];
+    policy().sortForEviction(&mut clips);
+    let order: Vec<&str> = clips.iter().map(|c| c.fileName.as_str()).collect();
+    assert_eq!(order, ["motion_old", "motion_new", "unknown", "gui_old", "telegram_new"]);
+  }
+
+  #[test]
+  fn evictsClipsWithoutMaxAgeLastOldestFirst() {
+    let mut policy = policy();
+    policy.config.retention.max_age_days = 0;
+    let mut clips = vec![
+      clip("unknown_new", TriggerSource::Unknown, 1),
+      clip("unknown_old", TriggerSource::Unknown, 400),
+      clip("gui", TriggerSource::Gui, 80),
+    ];
+    policy.sortForEviction(&mut clips);
+    let order: Vec<&str> = clips.iter().map(|c| c.fileName.as_str()).collect();
+    assert_eq!(order, ["gui", "unknown_old", "unknown_new"]);
+    assert!(policy.expiry(&clips[1]).is_none());
+  }
+}
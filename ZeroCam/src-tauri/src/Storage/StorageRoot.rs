@@ -0,0 +1,294 @@
+use crate::Config::ConfigFile;
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::error::Error;
+use std::os::unix::fs::MetadataExt;
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const WRITE_TEST_FILE_NAME: &str = ".zerocam-write-test";
+const RECENT_EVENTS        : usize = 50;
+const MOVED_DIRECTORIES    : [&str; 3] = ["Clips/", "Previews/", "Snapshots/"]; // LiveRecording is wiped on every switch anyway
+
+static STORAGE_ROOT: OnceLock<StorageRoot> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageEventKind {
+  SwitchedToFallback,
+  Restored,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageEvent {
+  pub kind  : StorageEventKind,
+  pub root  : String, // the root in use after the switch
+  pub reason: String,
+  pub time  : DateTime<Utc>,
+}
+
+/// Where clips and LiveRecording live, the configured root (eg. a usb ssd) while it is mounted and
+/// writable, otherwise the internal lib/zerocam directory
+pub struct StorageRoot {
+  state : Mutex<RootState>,
+  events: broadcast::Sender<StorageEvent>,
+}
+
+struct RootState {
+  configured   : Option<PathBuf>,
+  fallback     : PathBuf,
+  usingFallback: bool,
+  recentEvents : Vec<StorageEvent>,
+}
+
+impl StorageRoot {
+  pub fn global() -> &'static StorageRoot {
+    STORAGE_ROOT.get_or_init(|| {
+      let (events, _) = broadcast::channel(16);
+      StorageRoot {
+        state : Mutex::new(RootState {
+          configured   : None,
+          fallback     : internalRoot(),
+          usingFallback: true,
+          recentEvents : Vec::new(),
+        }),
+        events: events,
+      }
+    })
+  }
+
+  /// Picks the starting root from the config and checks on it every check_interval_sec
+  pub fn start(&self, config: &ConfigFile) {
+    let configured = config.storage.root.trim();
+    if configured.is_empty() {
+      info!("Storage: no storage root configured, using {}", self.root().display());
+      if let Err(e) = self.createDirectories() {
+        error!("Storage: failed to create directories: {}", e);
+      }
+      return;
+    }
+
+    let configured = PathBuf::from(configured);
+    match checkWritable(&configured) {
+      Ok(()) => {
+        let mut state = self.state.lock().unwrap();
+        state.configured    = Some(configured.clone());
+        state.usingFallback = false;
+        info!("Storage: using {}", configured.display());
+      }
+      Err(reason) => {
+        self.state.lock().unwrap().configured = Some(configured.clone());
+        self.switch(StorageEventKind::SwitchedToFallback, reason);
+      }
+    }
+    if let Err(e) = self.createDirectories() {
+      error!("Storage: failed to create directories: {}", e);
+    }
+
+    let interval = Duration::from_secs(config.storage.check_interval_sec.max(1));
+    tokio::spawn(async move {
+      loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = tokio::task::spawn_blocking(|| StorageRoot::global().check()).await {
+          error!("Storage check panicked: {}", e);
+        }
+      }
+    });
+  }
+
+  pub fn root(&self) -> PathBuf {
+    let state = self.state.lock().unwrap();
+    match (&state.configured, state.usingFallback) {
+      (Some(configured), false) => configured.clone(),
+      _                         => state.fallback.clone(),
+    }
+  }
+
+  pub fn clipsPath(&self) -> PathBuf {
+    self.root().join("Clips/")
+  }
+
+  pub fn liveRecordingPath(&self) -> PathBuf {
+    self.root().join("LiveRecording/")
+  }
+
+  pub fn previewsPath(&self) -> PathBuf {
+    self.root().join("Previews/")
+  }
+
+  pub fn snapshotsPath(&self) -> PathBuf {
+    self.root().join("Snapshots/")
+  }
+
+  pub fn usingFallback(&self) -> bool {
+    let state = self.state.lock().unwrap();
+    state.configured.is_some() && state.usingFallback
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+    self.events.subscribe()
+  }
+
+  pub fn recentEvents(&self) -> Vec<StorageEvent> {
+    self.state.lock().unwrap().recentEvents.clone()
+  }
+
+  fn check(&self) {
+    let (configured, usingFallback) = {
+      let state = self.state.lock().unwrap();
+      match &state.configured {
+        Some(configured) => (configured.clone(), state.usingFallback),
+        None             => return,
+      }
+    };
+
+    match (checkWritable(&configured), usingFallback) {
+      (Err(reason), false) => self.switch(StorageEventKind::SwitchedToFallback, reason),
+      (Ok(()), true)       => {
+        if let Err(e) = self.createDirectoriesIn(&configured) {
+          warn!("Storage: {} is back but unusable: {}", configured.display(), e);
+          return;
+        }
+        self.switch(StorageEventKind::Restored, format!("{} is mounted and writable again", configured.display()));
+      }
+      _ => (),
+    }
+
+    if !self.usingFallback() {
+      for directory in MOVED_DIRECTORIES {
+        self.moveFromFallback(directory);
+      }
+    }
+  }
+
+  fn switch(&self, kind: StorageEventKind, reason: String) {
+    let event = {
+      let mut state = self.state.lock().unwrap();
+      state.usingFallback = kind == StorageEventKind::SwitchedToFallback;
+      let root = match (&state.configured, state.usingFallback) {
+        (Some(configured), false) => configured.clone(),
+        _                         => state.fallback.clone(),
+      };
+      let event = StorageEvent {
+        kind  : kind,
+        root  : root.display().to_string(),
+        reason: reason,
+        time  : Utc::now(),
+      };
+      state.recentEvents.push(event.clone());
+      if state.recentEvents.len() > RECENT_EVENTS { state.recentEvents.remove(0); }
+      event
+    };
+
+    match kind {
+      StorageEventKind::SwitchedToFallback => warn!("Storage: switched to fallback {}, {}", event.root, event.reason),
+      StorageEventKind::Restored           => info!("Storage: switched back to {}, {}", event.root, event.reason),
+    }
+    if let Err(e) = self.createDirectories() {
+      error!("Storage: failed to create directories: {}", e);
+    }
+    let _ = self.events.send(event); //nobody listening is fine
+  }
+
+  /// Clips written to the fallback while the media was away go back to the media
+  fn moveFromFallback(&self, directory: &str) {
+    let fallbackPath = self.state.lock().unwrap().fallback.join(directory);
+    let targetPath = self.root().join(directory);
+    let entries = match fs::read_dir(&fallbackPath) {
+      Ok(entries) => entries,
+      Err(_)      => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+      let fileName = entry.file_name().to_string_lossy().to_string();
+      if fileName.starts_with('.') || !entry.path().is_file() { continue; }
+      match moveFile(&entry.path(), &targetPath.join(&fileName)) {
+        Ok(())  => info!("Storage: moved {} back to {}", fileName, targetPath.display()),
+        Err(e)  => error!("Storage: failed to move {} back to {}: {}", fileName, targetPath.display(), e),
+      }
+    }
+  }
+
+  fn createDirectories(&self) -> Result<(), Box<dyn Error>> {
+    self.createDirectoriesIn(&self.root())
+  }
+
+  fn createDirectoriesIn(&self, root: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(root.join("Clips/"))?;
+    fs::create_dir_all(root.join("Previews/"))?;
+    fs::create_dir_all(root.join("Snapshots/"))?;
+    fs::create_dir_all(root.join("LiveRecording/"))?;
+    Ok(())
+  }
+}
+
+/// The configured root must already exist and be a mount point, it is never created so an unmounted
+/// usb stick can't silently fill the sd card through its empty mount point
+fn checkWritable(root: &Path) -> Result<(), String> {
+  if !root.is_dir() {
+    return Err(format!("{} is missing, is the drive mounted?", root.display()));
+  }
+  if !isMountPoint(root) {
+    return Err(format!("{} is not a mount point, is the drive mounted?", root.display()));
+  }
+  let testPath = root.join(WRITE_TEST_FILE_NAME);
+  fs::write(&testPath, b"zerocam")
+    .and_then(|_| fs::remove_file(&testPath))
+    .map_err(|e| format!("{} is not writable: {}", root.display(), e))
+}
+
+/// A mount point is on another device than the directory it is in, `/` always is one
+fn isMountPoint(path: &Path) -> bool {
+  let path = match fs::canonicalize(path) {
+    Ok(path) => path,
+    Err(_)   => return false,
+  };
+  let parent = match path.parent() {
+    Some(parent) => parent,
+    None         => return true,
+  };
+  match (fs::metadata(&path), fs::metadata(parent)) {
+    (Ok(own), Ok(parent)) => own.dev() != parent.dev(),
+    _                     => false,
+  }
+}
+
+/// The hidden name a file is written under until it is complete, the catalog, backup, retention
+/// policy and file listener all skip names starting with a dot
+pub fn partPath(path: &Path) -> PathBuf {
+  path.with_file_name(format!(".{}.part", path.file_name().unwrap_or_default().to_string_lossy()))
+}
+
+/// rename only works within one filesystem, across disks the file is copied then removed
+pub fn moveFile(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+  if fs::rename(from, to).is_ok() { return Ok(()); }
+  let partPath = partPath(to);
+  fs::copy(from, &partPath)?;
+  fs::rename(&partPath, to)?;
+  fs::remove_file(from)?;
+  Ok(())
+}
+
+fn internalRoot() -> PathBuf {
+  env::current_dir()
+    .ok()
+    .and_then(|d| d.parent().map(|p| p.join("lib/zerocam/")))
+    .unwrap_or_else(|| PathBuf::from("../lib/zerocam/"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn findsMountPoints() {
+    assert!(isMountPoint(Path::new("/")));
+    assert!(!isMountPoint(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src")));
+    assert!(!isMountPoint(Path::new("/nonexistent/zerocam")));
+  }
+}
@@ -0,0 +1,6 @@
+pub mod ClipCatalog;
+pub mod ClipEncryption;
+pub mod ClipLedger;
+pub mod DiskUsage;
+pub mod RetentionPolicy;
+pub mod StorageRoot;
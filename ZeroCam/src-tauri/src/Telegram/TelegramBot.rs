@@ -1,9 +1,10 @@
-use crate::Config;
-use crate::Config::ConfigFile;
+use zerocam_lib::Config;
+use zerocam_lib::Config::ConfigFile;
+use chrono::{DateTime, Utc};
 use std::env;
 use std::error::Error;
 use std::sync::Arc;
-use teloxide::{prelude::*, utils::command::BotCommands};
+use teloxide::{prelude::*, types::InputFile, utils::command::BotCommands};
 
 #[derive(BotCommands, Clone)]
 #[command(
@@ -13,10 +14,24 @@ use teloxide::{prelude::*, utils::command::BotCommands};
 enum Command {
     #[command(description = "list of commands.")]
     Start,
-    #[command(description = "clip.")]
-    Clip,
+    #[command(description = "clip every camera, or only the camera named. Add seconds to export the last seconds instead, eg. /clip front 90, and exact to cut on the exact frame.")]
+    Clip(String),
+    #[command(description = "take a photo with every camera, or only the camera named.")]
+    Snapshot(String),
     #[command(description = "get stream url.")]
     Stream,
+    #[command(description = "disk space used by clips and recordings.")]
+    Storage,
+    #[command(description = "lock a clip so it is never deleted, the newest clip if no name is given.")]
+    Lock(String),
+    #[command(description = "unlock a clip so it can be deleted to make space.")]
+    Unlock(String),
+    #[command(description = "stop recording cabin audio.")]
+    Mute,
+    #[command(description = "record cabin audio again.")]
+    Unmute,
+    #[command(description = "keep all footage as archive files: off, always or driving. Shows the mode if none is given.")]
+    Archive(String),
 }
 
 pub async fn newBot() -> Result<(), Box<dyn Error>> {
@@ -33,34 +48,202 @@ async fn answer(bot: Bot, msg: Message, cmd: Command) -> ResponseResult<()> {
             bot.send_message(msg.chat.id, Command::descriptions().to_string())
                 .await?
         }
-        Command::Clip => {
+        Command::Clip(args) => {
             bot.send_message(msg.chat.id, "Attempting to make a clip...")
                 .await?;
-            if clipScheduler.scheduleClip().await.is_ok() {
-                bot.send_message(
-                    msg.chat.id,
-                    "Clip successful, should be visible in google drive soon...",
-                )
-                .await?
-            } else {
-                bot.send_message(msg.chat.id, "Already in progress - try again later")
-                    .await?
+            let trigger = clipTrigger(&args, msg.date);
+            let result = clipScheduler
+                .scheduleClip(trigger)
+                .await
+                .map_err(|e| e.to_string());
+            match result {
+                Ok(clips) => {
+                    let mut last = None;
+                    for clip in clips {
+                        let caption = format!(
+                            "Clip saved: {} ({:.1}MB, {:.0}sec), should be visible in google drive soon...",
+                            clip.fileName,
+                            clip.sizeB as f64 / (1024.0 * 1024.0),
+                            clip.durationSec
+                        );
+                        last = Some(match clip.previewPath {
+                            Some(previewPath) => {
+                                bot.send_animation(msg.chat.id, InputFile::file(previewPath))
+                                    .caption(caption)
+                                    .await?
+                            }
+                            None => bot.send_message(msg.chat.id, caption).await?,
+                        });
+                    }
+                    match last {
+                        Some(sent) => sent,
+                        None => bot.send_message(msg.chat.id, "No clips were made").await?,
+                    }
+                }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, format!("Clip failed: {}", e))
+                        .await?
+                }
+            }
+        }
+        Command::Snapshot(camera) => {
+            let camera = Some(camera.trim()).filter(|c| !c.is_empty());
+            let result = zerocam_lib::Camera::Snapshot::takeSnapshots(camera)
+                .await
+                .map_err(|e| e.to_string());
+            match result {
+                Ok(snapshots) => {
+                    let mut last = None;
+                    for snapshot in snapshots {
+                        last = Some(
+                            bot.send_photo(msg.chat.id, InputFile::file(snapshot.path))
+                                .caption(format!("{} at {}", snapshot.camera, snapshot.takenAt))
+                                .await?,
+                        );
+                    }
+                    match last {
+                        Some(sent) => sent,
+                        None => bot.send_message(msg.chat.id, "No snapshots were taken").await?,
+                    }
+                }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, format!("Snapshot failed: {}", e))
+                        .await?
+                }
             }
         }
         Command::Stream => {
             let config: ConfigFile = Config::getConfig().await.unwrap();
-            let streamUrl = config.internet_stream_output.url;
+            let streamUrls: Vec<String> = config
+                .camera_input
+                .iter()
+                .map(|camera| {
+                    let baseUrl = config.internet_stream_output.url.trim_end_matches('/');
+                    format!("{}: {}/{}/", camera.name, baseUrl, camera.name)
+                })
+                .collect();
             let username = config.internet_stream_output.username;
             bot.send_message(
                 msg.chat.id,
                 format!(
-                    "Click link to view stream: {} \nUsername: {}",
-                    streamUrl, username
+                    "Click link to view stream: \n{} \nUsername: {}",
+                    streamUrls.join("\n"), username
                 ),
             )
             .await?
         }
+        Command::Storage => {
+            let reply = match zerocam_lib::Storage::DiskUsage::currentStorageStatus() {
+                Ok(status) => {
+                    let mut reply = format!(
+                        "Clips: {}\nLive recording: {}",
+                        describeVolume(&status.clips),
+                        describeVolume(&status.liveRecording)
+                    );
+                    if status.sameVolume {
+                        reply += "\nBoth share the same disk";
+                    }
+                    reply
+                }
+                Err(e) => format!("Failed to read storage: {}", e),
+            };
+            bot.send_message(msg.chat.id, reply).await?
+        }
+        Command::Lock(fileName) => {
+            let reply = setClipLocked(fileName, true);
+            bot.send_message(msg.chat.id, reply).await?
+        }
+        Command::Unlock(fileName) => {
+            let reply = setClipLocked(fileName, false);
+            bot.send_message(msg.chat.id, reply).await?
+        }
+        Command::Mute => {
+            let reply = setAudioMuted(true).await;
+            bot.send_message(msg.chat.id, reply).await?
+        }
+        Command::Unmute => {
+            let reply = setAudioMuted(false).await;
+            bot.send_message(msg.chat.id, reply).await?
+        }
+        Command::Archive(mode) => {
+            let reply = setArchiveMode(&mode);
+            bot.send_message(msg.chat.id, reply).await?
+        }
     };
 
     Ok(())
 }
+
+/// Arguments in any order, a camera name, a number of seconds to export and "exact"
+fn clipTrigger(args: &str, sentAt: DateTime<Utc>) -> zerocam_lib::ClipTrigger {
+    let mut camera = None;
+    let mut seconds = None;
+    let mut exact = false;
+    for arg in args.split_whitespace() {
+        match arg.parse::<u32>() {
+            Ok(parsed) => seconds = Some(parsed),
+            Err(_) if arg.eq_ignore_ascii_case("exact") => exact = true,
+            Err(_) => camera = Some(arg.to_string()),
+        }
+    }
+    let trigger = match seconds {
+        Some(seconds) => zerocam_lib::ClipTrigger::exportLast(zerocam_lib::TriggerSource::Telegram, seconds),
+        None => zerocam_lib::ClipTrigger::at(zerocam_lib::TriggerSource::Telegram, sentAt),
+    }
+    .frameAccurate(exact);
+    match camera {
+        Some(camera) => trigger.forCamera(camera),
+        None => trigger,
+    }
+}
+
+fn setClipLocked(fileName: String, locked: bool) -> String {
+    let catalog = zerocam_lib::ClipCatalog::global();
+    let fileName = match fileName.trim() {
+        "" => match catalog.newest() {
+            Ok(Some(clip)) => clip.fileName,
+            Ok(None) => return "There are no clips yet".to_string(),
+            Err(e) => return format!("Failed to read clips: {}", e),
+        },
+        name => name.to_string(),
+    };
+    match catalog.setLocked(&fileName, locked) {
+        Ok(clip) if clip.locked => format!("Clip locked: {}", clip.fileName),
+        Ok(clip) => format!("Clip unlocked: {}", clip.fileName),
+        Err(e) => format!("Failed to {} clip: {}", if locked { "lock" } else { "unlock" }, e),
+    }
+}
+
+async fn setAudioMuted(muted: bool) -> String {
+    let result = zerocam_lib::Camera::CameraController::setAudioMuted(muted)
+        .await
+        .map_err(|e| e.to_string());
+    match result {
+        Ok(()) if muted => "Audio muted, clips will be silent".to_string(),
+        Ok(()) => "Audio unmuted".to_string(),
+        Err(e) => format!("Failed to {} audio: {}", if muted { "mute" } else { "unmute" }, e),
+    }
+}
+
+fn setArchiveMode(mode: &str) -> String {
+    let mode = match mode.trim().to_lowercase().as_str() {
+        "" => return format!("Archive mode: {:?}", zerocam_lib::Camera::ArchiveRecorder::getArchiveMode()),
+        "off" => zerocam_lib::ArchiveMode::Off,
+        "always" => zerocam_lib::ArchiveMode::Always,
+        "driving" => zerocam_lib::ArchiveMode::Driving,
+        other => return format!("Unknown archive mode {}, use off, always or driving", other),
+    };
+    zerocam_lib::Camera::ArchiveRecorder::setArchiveMode(mode);
+    format!("Archive mode set to {:?}", mode)
+}
+
+fn describeVolume(volume: &zerocam_lib::Storage::DiskUsage::VolumeUsage) -> String {
+    const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+    format!(
+        "{:.2}GB used, {:.1}GB of {:.1}GB free on {}",
+        volume.directoryB as f64 / GB,
+        volume.availableB as f64 / GB,
+        volume.totalB as f64 / GB,
+        volume.mountPoint
+    )
+}
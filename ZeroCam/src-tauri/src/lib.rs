@@ -4,23 +4,72 @@ pub mod Camera;
 pub mod Config;
 pub mod GDFiles;
 pub mod Net;
+pub mod Process;
+pub mod Storage;
 
-pub use crate::Camera::ClipScheduler::ClipScheduler;
+pub use crate::Camera::CameraController::ClipResult;
+pub use crate::Camera::ClipScheduler::{ClipRange, ClipScheduler, ClipTrigger, TriggerSource};
 pub use crate::Camera::MotionListener::MotionListener;
+pub use crate::Camera::SegmentRing::{SegmentRing, SegmentRingStatus};
+pub use crate::Camera::Snapshot::Snapshot;
 pub use crate::Net::NetworkConnector::getKnownNetworks;
-pub use crate::Config::getConfigAsString;
+pub use crate::Config::{getConfigAsString, ArchiveMode};
 pub use crate::Config::setConfigFromString;
+pub use crate::Process::Supervisor::{ProcessHealth, Supervisor};
+pub use crate::Storage::ClipCatalog::{ClipCatalog, ClipRecord};
+pub use crate::Storage::DiskUsage::StorageStatus;
+pub use crate::Storage::StorageRoot::{StorageEvent, StorageRoot};
 
+use chrono::DateTime;
 use log::{error};
+use std::collections::BTreeMap;
 use std::process::Command;
 use std::sync::{Arc, OnceLock};
-use tauri::Manager;
+use tauri::{Emitter, Manager, RunEvent};
+use tokio::sync::broadcast::error::RecvError;
 
 static previousNetworkState: OnceLock<Vec<String>> = OnceLock::new();
 
 #[tauri::command]
-async fn feScheduleClip(state: tauri::State<'_, Arc<ClipScheduler>>) -> Result<(), String> {
-  state.scheduleClip().await.map_err(|e| e.to_string())
+async fn feScheduleClip(state: tauri::State<'_, Arc<ClipScheduler>>, triggeredAtMs: Option<i64>, camera: Option<String>) -> Result<Vec<ClipResult>, String> {
+  let trigger = match triggeredAtMs.and_then(DateTime::from_timestamp_millis) {
+    Some(time) => ClipTrigger::at(TriggerSource::Gui, time),
+    None       => ClipTrigger::now(TriggerSource::Gui),
+  };
+  let trigger = match camera {
+    Some(camera) => trigger.forCamera(camera),
+    None         => trigger,
+  };
+  state.scheduleClip(trigger).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn feExportClip(state: tauri::State<'_, Arc<ClipScheduler>>, fromMs: i64, toMs: i64, camera: Option<String>, frameAccurate: Option<bool>) -> Result<Vec<ClipResult>, String> {
+  let (from, to) = match (DateTime::from_timestamp_millis(fromMs), DateTime::from_timestamp_millis(toMs)) {
+    (Some(from), Some(to)) => (from, to),
+    _                      => return Err(format!("Invalid export range {} to {}", fromMs, toMs)),
+  };
+  let trigger = ClipTrigger::export(TriggerSource::Gui, from, to).frameAccurate(frameAccurate.unwrap_or(false));
+  let trigger = match camera {
+    Some(camera) => trigger.forCamera(camera),
+    None         => trigger,
+  };
+  state.scheduleClip(trigger).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn feExportLast(state: tauri::State<'_, Arc<ClipScheduler>>, seconds: u32, camera: Option<String>, frameAccurate: Option<bool>) -> Result<Vec<ClipResult>, String> {
+  let trigger = ClipTrigger::exportLast(TriggerSource::Gui, seconds).frameAccurate(frameAccurate.unwrap_or(false));
+  let trigger = match camera {
+    Some(camera) => trigger.forCamera(camera),
+    None         => trigger,
+  };
+  state.scheduleClip(trigger).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn feTakeSnapshot(camera: Option<String>) -> Result<Vec<Snapshot>, String> {
+  Camera::Snapshot::takeSnapshots(camera.as_deref()).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -33,6 +82,26 @@ fn feGetParked() -> bool {
   Camera::MotionListener::getParkedState()
 }
 
+#[tauri::command]
+fn feSetArchiveMode(mode: ArchiveMode) {
+  Camera::ArchiveRecorder::setArchiveMode(mode);
+}
+
+#[tauri::command]
+fn feGetArchiveMode() -> ArchiveMode {
+  Camera::ArchiveRecorder::getArchiveMode()
+}
+
+#[tauri::command]
+async fn feSetMuted(muted: bool) -> Result<(), String> {
+  Camera::CameraController::setAudioMuted(muted).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn feGetMuted() -> bool {
+  Camera::AudioSource::getMutedState()
+}
+
 #[tauri::command]
 fn feRebootSystem() {
   Command::new("sudo").arg("reboot").spawn().expect("failed to reboot");
@@ -43,6 +112,41 @@ async fn feGetKnownNetworks() -> Result<Vec<String>, String> {
   getKnownNetworks().await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn feGetProcessHealth() -> Vec<ProcessHealth> {
+  Supervisor::global().health()
+}
+
+#[tauri::command]
+fn feGetSegmentRingStatus() -> BTreeMap<String, SegmentRingStatus> {
+  SegmentRing::statuses()
+}
+
+#[tauri::command]
+fn feGetStorageStatus() -> Result<StorageStatus, String> {
+  Storage::DiskUsage::currentStorageStatus().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn feGetStorageEvents() -> Vec<StorageEvent> {
+  StorageRoot::global().recentEvents()
+}
+
+#[tauri::command]
+fn feGetClips() -> Result<Vec<ClipRecord>, String> {
+  ClipCatalog::global().all().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn feLockClip(fileName: String) -> Result<ClipRecord, String> {
+  ClipCatalog::global().setLocked(&fileName, true).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn feUnlockClip(fileName: String) -> Result<ClipRecord, String> {
+  ClipCatalog::global().setLocked(&fileName, false).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn feGetConfig() -> Result<String, String>{
   getConfigAsString().await.map_err(|e| e.to_string())
@@ -56,15 +160,50 @@ fn feSetConfig(config: String) -> Result<(), String> {
 pub fn run(clipScheduler: Arc<ClipScheduler>) {
   tauri::Builder::default()
     .manage(clipScheduler)
+    .setup(|app| {
+      let handle = app.handle().clone();
+      let mut storageEvents = StorageRoot::global().subscribe();
+      tauri::async_runtime::spawn(async move {
+        loop {
+          match storageEvents.recv().await {
+            Ok(event) => if let Err(e) = handle.emit("storage-event", event) {
+              error!("Failed to send storage event to the GUI: {}", e);
+            },
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed)    => break,
+          }
+        }
+      });
+      Ok(())
+    })
     .invoke_handler(tauri::generate_handler![
       feScheduleClip,
+      feExportClip,
+      feExportLast,
+      feTakeSnapshot,
       feSetParked,
       feGetConfig,
       feSetConfig,
       feGetParked,
+      feSetArchiveMode,
+      feGetArchiveMode,
+      feSetMuted,
+      feGetMuted,
       feGetKnownNetworks,
+      feGetProcessHealth,
+      feGetSegmentRingStatus,
+      feGetStorageStatus,
+      feGetStorageEvents,
+      feGetClips,
+      feLockClip,
+      feUnlockClip,
       feRebootSystem
     ])
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|_, event| {
+      if let RunEvent::Exit = event { //tauri exits the process right after, so the children are stopped here
+        tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(Supervisor::global().shutdown()));
+      }
+    });
 }
@@ -1,20 +1,23 @@
 #![allow(non_snake_case)]
-pub mod Camera;
-mod Config;
-mod GDFiles;
-mod Net;
 mod Telegram;
 
-use zerocam_lib::Camera::CameraController::CameraController;
+use zerocam_lib::Camera::ArchiveRecorder::ArchiveRecorder;
+use zerocam_lib::Camera;
 use zerocam_lib::Camera::ClipScheduler::ClipScheduler;
 use zerocam_lib::Camera::MotionListener::MotionListener;
+use zerocam_lib::Camera::TimelapseRecorder::TimelapseRecorder;
+use zerocam_lib::Config;
 use zerocam_lib::GDFiles::BackupScheduler::BackupScheduler;
 use zerocam_lib::GDFiles::FileListener::FileListener;
 use zerocam_lib::Net::ConnectionListener::listen;
+use zerocam_lib::Process::Supervisor::Supervisor;
+use zerocam_lib::Storage::ClipCatalog::ClipCatalog;
+use zerocam_lib::Storage::RetentionPolicy::RetentionPolicy;
+use zerocam_lib::Storage::StorageRoot::StorageRoot;
 use crate::Telegram::TelegramBot;
 
 use env_logger;
-use log::info;
+use log::{error, info};
 use std::error::Error;
 use std::sync::Arc;
 use std::thread;
@@ -25,6 +28,20 @@ use tokio::{signal, task};
 
 #[tokio::main]
 async fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  let subcommand = match args.get(1).map(String::as_str) {
+    Some("decrypt") => Some(zerocam_lib::Storage::ClipEncryption::decryptCommand(&args[2..])), //ZeroCam decrypt <identity file> <clip.age>...
+    Some("verify")  => Some(zerocam_lib::Storage::ClipLedger::verifyCommand(&args[2..])),     //ZeroCam verify <clip>...
+    _               => None,
+  };
+  if let Some(result) = subcommand {
+    if let Err(e) = result {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+
   env_logger::init();
   rustls::crypto::ring::default_provider()
     .install_default()
@@ -32,6 +49,18 @@ async fn main() {
 
   Config::showConfig().await;
 
+  StorageRoot::global().start(&Config::getConfig().await.unwrap());
+  if let Err(e) = ClipCatalog::global().reconcile(&StorageRoot::global().clipsPath()) {
+    error!("Failed to reconcile clip catalog: {}", e);
+  }
+
+  let retentionPolicy = Arc::new(RetentionPolicy::new().await.unwrap());
+  if let Err(e) = retentionPolicy.enforce(0).await {
+    error!("Retention check failed: {}", e);
+  }
+  retentionPolicy.startTimer();
+  info!("Retention policy running.");
+
   let backupScheduler = Arc::new(BackupScheduler::new().await.unwrap());
 
   let _fileListener = FileListener::new(backupScheduler.clone()).await.unwrap();
@@ -47,6 +76,12 @@ async fn main() {
     .unwrap();
   info!("Camera live.");
 
+  Arc::new(ArchiveRecorder::new().await.unwrap()).start();
+  info!("Archive Recorder running.");
+
+  Arc::new(TimelapseRecorder::new().await.unwrap()).start();
+  info!("Timelapse Recorder running.");
+
   let clipScheduler = Arc::new(ClipScheduler::new().await); //zerocam_lib necessary as tauri gets confused
 
   let _telegramBot = tokio::spawn(async move {
@@ -54,21 +89,29 @@ async fn main() {
   });
   info!("Telegram bot live.");
 
-  let motionListener = MotionListener::new(clipScheduler.clone())
-    .await
-    .unwrap();
+  let mut motionListeners: Vec<MotionListener> = Vec::new();
+  for camera in Config::getConfig().await.unwrap().camera_input { //one listener per camera on its own motion feed
+    motionListeners.push(MotionListener::new(clipScheduler.clone(), camera.name)
+      .await
+      .unwrap());
+  }
 
   sleep(Duration::from_secs(5));
-  let _motionListenerProcess = tokio::spawn(async move {
-    motionListener.run().await;
-  });
-  info!("Motion Listener running.");
-
-  zerocam_lib::run(clipScheduler);
+  for motionListener in motionListeners {
+    tokio::spawn(async move {
+      motionListener.run().await;
+    });
+  }
+  info!("Motion Listeners running.");
 
-  signal::ctrl_c()
-    .await
-    .expect("Failed to install Ctrl+C signal handler");
+  tokio::spawn(async {
+    signal::ctrl_c()
+      .await
+      .expect("Failed to install Ctrl+C signal handler");
+    Supervisor::global().shutdown().await;
+    info!("Shutting Down!");
+    std::process::exit(0);
+  });
 
-  info!("Shutting Down!")
+  zerocam_lib::run(clipScheduler); //never returns, the children are stopped when tauri exits
 }
@@ -5,14 +5,17 @@ import "./global.css";
 import Hls from "hls.js"
 import {useEffect, useRef, useState} from "react";
 import {Link} from "react-router-dom";
+import {load} from "js-yaml";
+import {Config} from "./ConfigModel";
 import icon64 from './assets/64x64.png';
 
 function App() {
   const videoRef = useRef<HTMLVideoElement>(null);
   let [parked, setParked] = useState<boolean>(false)
+  let [camera, setCamera] = useState<string>()
 
   function scheduleClip() {
-    invoke('feScheduleClip');
+    invoke('feScheduleClip', {triggeredAtMs: Date.now()});
   }
 
   function makeParked(p: boolean) {
@@ -38,16 +41,24 @@ function App() {
     return () => clearInterval(intervalId);
   }, []);
 
+  useEffect(() => {
+    async function initCamera() {
+      const fileContents: string = await invoke("feGetConfig");
+      setCamera((load(fileContents) as Config).camera_input[0]?.name);
+    }
+    initCamera()
+  }, []);
+
   useEffect(() => {
     const video = videoRef.current;
-    if (!video) return;
+    if (!video || !camera) return;
 
     const hls = new Hls({
       lowLatencyMode : true
     });
 
     const loadStream = () => {
-      hls.loadSource("http://localhost:8888/stream1/index.m3u8");
+      hls.loadSource(`http://localhost:8888/${camera}/index.m3u8`);
       hls.attachMedia(video);
     };
 
@@ -64,7 +75,7 @@ function App() {
     return () => {
       hls.destroy();
     };
-  }, []);
+  }, [camera]);
 
   return (
     <main className="container-fluid">
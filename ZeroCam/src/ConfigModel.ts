@@ -1,51 +1,114 @@
 export interface Config {
   telegram_key          : string;
-  camera_input          : CameraInput;
+  camera_input          : Array<CameraInput>;
+  clip                  : Clip;
+  archive               : Archive;
+  timelapse             : Timelapse;
   motion_listener       : MotionListener;
   gui_stream_output     : GUIStreamOutput;
   internet_stream_output: InternetStreamOutput;
   g_cloud               : GCloud;
+  retention             : Retention;
+  storage               : Storage;
+  encryption            : Encryption;
+  ledger                : Ledger;
+  overlay               : Overlay;
   hotspot_networks      : Array<string>;
 }
 
 interface CameraInput {
-  resolution: string;
-  fps       : string;
-  encoder   : string;
-  clip      : Clip;
+  name       : string;
+  source     : VideoSource;
+  resolution : string;
+  fps        : string;
+  encoding   : Encoding;
+  orientation: Orientation;
+  audio?     : Audio | null;
 }
 
+interface Encoding {
+  codec    : string;
+  preset   : string;
+  bit_rate : string;
+  pix_fmt  : string;
+  grayscale: boolean;
+}
+
+interface Orientation {
+  rotate: number;
+  hflip : boolean;
+  vflip : boolean;
+}
+
+type VideoSource =
+  | { type: "v4l2"; device: string; input_format: string }
+  | { type: "stream"; url: string }
+  | { type: "file"; path: string }
+  | { type: "test_src" };
+
+interface Audio {
+  source     : AudioSource;
+  codec      : string;
+  bit_rate   : string;
+  sample_rate: string;
+  muted      : boolean;
+}
+
+type AudioSource =
+  | { type: "alsa"; device: string }
+  | { type: "pulse"; device: string }
+  | { type: "sine"; frequency: number }
+  | { type: "anullsrc" };
+
 interface Clip {
   segment_size_sec     : number;
   segments             : number;
-  timer_before_clip_sec: number;
+  pre_event_sec        : number;
+  post_event_sec       : number;
   cooldown_sec         : number;
   disk_full_buffer_gb  : number;
+  segment_format       : "mpegts" | "fmp4" | "mkv";
+  clip_format          : "mp4" | "fmp4" | "mkv";
+}
+
+interface Archive {
+  mode      : "off" | "always" | "driving";
+  period_min: number;
+}
+
+interface Timelapse {
+  enabled     : boolean;
+  interval_sec: number;
+  fps         : number;
 }
 
 interface MotionListener {
+  algorithm           : "frame_diff" | "mog2" | "knn" | "optical_flow";
   sensitivity_inverse : number;
+  flow_threshold_px   : number;
   threshold_sum_kilo  : number;
   frame_delay_millisec: number;
   trigger_duration    : number;
   resolution          : string;
-  bit_rate            : string;
   fps                 : string;
+  orientation         : Orientation;
 }
 
 interface GUIStreamOutput {
-  resolution: string;
-  bit_rate  : string;
-  fps       : string;
+  resolution : string;
+  fps        : string;
+  encoding   : Encoding;
+  orientation: Orientation;
 }
 
 interface InternetStreamOutput {
-  url       : string;
-  username  : string;
-  password  : string;
-  resolution: string;
-  bit_rate  : string;
-  fps       : string;
+  url        : string;
+  username   : string;
+  password   : string;
+  resolution : string;
+  fps        : string;
+  encoding   : Encoding;
+  orientation: Orientation;
 }
 
 interface GCloud {
@@ -53,5 +116,43 @@ interface GCloud {
   backup_scheduler_timeout_sec: number;
 }
 
+interface Retention {
+  check_interval_sec: number;
+  max_age_days      : number;
+  max_clips         : number;
+  max_total_gb      : number;
+  triggers          : Partial<Record<TriggerSource, TriggerQuota>>;
+}
+
+type TriggerSource = "motion" | "gui" | "telegram" | "api" | "archive" | "timelapse" | "unknown";
 
+interface TriggerQuota {
+  max_age_days: number;
+  max_clips   : number;
+}
+
+interface Storage {
+  root              : string;
+  check_interval_sec: number;
+}
 
+interface Encryption {
+  enabled             : boolean;
+  recipient           : string;
+  keep_local_plaintext: boolean;
+}
+
+interface Ledger {
+  sign: boolean;
+}
+
+interface Overlay {
+  device_label   : string;
+  timezone       : string;
+  time_format    : string;
+  font_size      : number;
+  font_file      : string;
+  storage        : boolean;
+  internet_stream: boolean;
+  gui_stream     : boolean;
+}
@@ -39,17 +39,19 @@ function Settings(){
 
       config.g_cloud.limit_gb = Number(extractField("g_cloud.limit_gb"));
 
-      config.camera_input.clip.disk_full_buffer_gb = Number(extractField("camera_input.clip.disk_full_buffer_gb"));
+      config.clip.disk_full_buffer_gb = Number(extractField("clip.disk_full_buffer_gb"));
+      config.retention.max_age_days   = Number(extractField("retention.max_age_days"));
 
       config.motion_listener.sensitivity_inverse  = Number(extractField("motion_listener.sensitivity_inverse"));
       config.motion_listener.threshold_sum_kilo   = Number(extractField("motion_listener.threshold_sum_kilo"));
       config.motion_listener.frame_delay_millisec = Number(extractField("motion_listener.frame_delay_millisec"));
       config.motion_listener.trigger_duration     = Number(extractField("motion_listener.trigger_duration"));
 
-      config.camera_input.clip.segment_size_sec      = Number(extractField("camera_input.clip.segment_size_sec"));
-      config.camera_input.clip.segments              = Number(extractField("camera_input.clip.segments"));
-      config.camera_input.clip.timer_before_clip_sec = Number(extractField("camera_input.clip.timer_before_clip_sec"));
-      config.camera_input.clip.cooldown_sec          = Number(extractField("camera_input.clip.cooldown_sec"));
+      config.clip.segment_size_sec = Number(extractField("clip.segment_size_sec"));
+      config.clip.segments         = Number(extractField("clip.segments"));
+      config.clip.pre_event_sec    = Number(extractField("clip.pre_event_sec"));
+      config.clip.post_event_sec   = Number(extractField("clip.post_event_sec"));
+      config.clip.cooldown_sec     = Number(extractField("clip.cooldown_sec"));
 
       config.telegram_key = extractField("telegram_key").toString();
       config.internet_stream_output.url = extractField("internet_stream_output.url").toString();
@@ -123,13 +125,24 @@ function Settings(){
             <td className="col h3">
               Storage Full Buffer </td>
             <td className="w-25 pt-1 pb-1">
-              <input className="form-control form-control-lg text-white" id="camera_input.clip.disk_full_buffer_gb"
-                placeholder={`${config?.camera_input.clip.disk_full_buffer_gb ?? ""}`}
-                defaultValue={`${config?.camera_input.clip.disk_full_buffer_gb ?? ""}`}
+              <input className="form-control form-control-lg text-white" id="clip.disk_full_buffer_gb"
+                placeholder={`${config?.clip.disk_full_buffer_gb ?? ""}`}
+                defaultValue={`${config?.clip.disk_full_buffer_gb ?? ""}`}
               />
             </td>
             <td className="h3 w-25">GB</td>
           </tr>
+          <tr>
+            <td className="col h3">
+              Keep Clips For </td>
+            <td className="w-25 pt-1 pb-1">
+              <input className="form-control form-control-lg text-white" id="retention.max_age_days"
+                placeholder={`${config?.retention.max_age_days ?? ""}`}
+                defaultValue={`${config?.retention.max_age_days ?? ""}`}
+              />
+            </td>
+            <td className="h3 w-25">days</td>
+          </tr>
         </tbody>
       </table>
     )
@@ -196,9 +209,9 @@ function Settings(){
           <td className="col h3">
             Segment Length </td>
           <td className="w-25 pt-1 pb-1">
-            <input className="form-control form-control-lg text-white" id="camera_input.clip.segment_size_sec"
-              placeholder={`${config?.camera_input.clip.segment_size_sec ?? ""}`}
-              defaultValue={`${config?.camera_input.clip.segment_size_sec ?? ""}`}
+            <input className="form-control form-control-lg text-white" id="clip.segment_size_sec"
+              placeholder={`${config?.clip.segment_size_sec ?? ""}`}
+              defaultValue={`${config?.clip.segment_size_sec ?? ""}`}
             />
           </td>
           <td className="h3 w-25">sec</td>
@@ -207,20 +220,31 @@ function Settings(){
           <td className="col h3">
             Segments </td>
           <td className="w-25 pt-1 pb-1">
-            <input className="form-control form-control-lg text-white" id="camera_input.clip.segments"
-              placeholder={`${config?.camera_input.clip.segments ?? ""}`}
-              defaultValue={`${config?.camera_input.clip.segments ?? ""}`}
+            <input className="form-control form-control-lg text-white" id="clip.segments"
+              placeholder={`${config?.clip.segments ?? ""}`}
+              defaultValue={`${config?.clip.segments ?? ""}`}
             />
           </td>
           <td className="h3 w-25"></td>
         </tr>
         <tr>
           <td className="col h3">
-            Timer before clip </td>
+            Clip before event </td>
+          <td className="w-25 pt-1 pb-1">
+            <input className="form-control form-control-lg text-white" id="clip.pre_event_sec"
+              placeholder={`${config?.clip.pre_event_sec ?? ""}`}
+              defaultValue={`${config?.clip.pre_event_sec ?? ""}`}
+            />
+          </td>
+          <td className="h3 w-25">sec</td>
+        </tr>
+        <tr>
+          <td className="col h3">
+            Clip after event </td>
           <td className="w-25 pt-1 pb-1">
-            <input className="form-control form-control-lg text-white" id="camera_input.clip.timer_before_clip_sec"
-              placeholder={`${config?.camera_input.clip.timer_before_clip_sec ?? ""}`}
-              defaultValue={`${config?.camera_input.clip.timer_before_clip_sec ?? ""}`}
+            <input className="form-control form-control-lg text-white" id="clip.post_event_sec"
+              placeholder={`${config?.clip.post_event_sec ?? ""}`}
+              defaultValue={`${config?.clip.post_event_sec ?? ""}`}
             />
           </td>
           <td className="h3 w-25">sec</td>
@@ -229,9 +253,9 @@ function Settings(){
           <td className="col h3">
             Clip cooldown </td>
           <td className="w-25 pt-1 pb-1">
-            <input className="form-control form-control-lg text-white" id="camera_input.clip.cooldown_sec"
-              placeholder={`${config?.camera_input.clip.cooldown_sec ?? ""}`}
-              defaultValue={`${config?.camera_input.clip.cooldown_sec ?? ""}`}
+            <input className="form-control form-control-lg text-white" id="clip.cooldown_sec"
+              placeholder={`${config?.clip.cooldown_sec ?? ""}`}
+              defaultValue={`${config?.clip.cooldown_sec ?? ""}`}
             />
           </td>
           <td className="h3 w-25">sec</td>